@@ -1,7 +1,7 @@
 #[cfg(feature = "android-jni")]
 use jni::objects::{JClass, JObject, JString, JValue};
 #[cfg(feature = "android-jni")]
-use jni::sys::{jboolean, jfloatArray, jint, jlong, jstring};
+use jni::sys::{jboolean, jbyteArray, jfloatArray, jint, jlong, jstring};
 #[cfg(feature = "android-jni")]
 use jni::JNIEnv;
 
@@ -9,7 +9,7 @@ use jni::JNIEnv;
 use std::panic::catch_unwind;
 
 #[cfg(feature = "android-jni")]
-use crate::{free_whisper, init_whisper, is_valid_model, process_audio, process_audio_sliding_window, WhisperError};
+use crate::{free_whisper, init_whisper, init_whisper_from_bytes, init_whisper_from_fd, is_valid_model, process_audio, process_audio_sliding_window, ContextOptions, WhisperError};
 
 #[cfg(target_os = "android")]
 pub fn init_android_logger() {
@@ -42,6 +42,57 @@ pub extern "system" fn Java_com_example_whisper_WhisperEngine_initWhisper(
     }
 }
 
+// Load a model held in a Java byte[] (e.g. read from an encrypted blob)
+#[cfg(feature = "android-jni")]
+#[no_mangle]
+pub extern "system" fn Java_com_example_whisper_WhisperEngine_initWhisperFromBytes(
+    env: JNIEnv,
+    _class: JClass,
+    model_data: jbyteArray,
+) -> jint {
+    let result = catch_unwind(|| {
+        let model_data = match env.convert_byte_array(model_data) {
+            Ok(data) => data,
+            Err(_) => return -1,
+        };
+        match init_whisper_from_bytes(&model_data, &ContextOptions::default()) {
+            Ok(id) => id,
+            Err(_) => -1,
+        }
+    });
+
+    match result {
+        Ok(id) => id,
+        Err(_) => -1,
+    }
+}
+
+// Load a model from an AssetFileDescriptor: pass getParcelFileDescriptor().getFd()
+// and getStartOffset(). The descriptor can be closed once this returns.
+#[cfg(feature = "android-jni")]
+#[no_mangle]
+pub extern "system" fn Java_com_example_whisper_WhisperEngine_initWhisperFromFd(
+    _env: JNIEnv,
+    _class: JClass,
+    fd: jint,
+    offset: jlong,
+) -> jint {
+    let result = catch_unwind(|| {
+        if offset < 0 {
+            return -1;
+        }
+        match init_whisper_from_fd(fd, offset as u64, &ContextOptions::default()) {
+            Ok(id) => id,
+            Err(_) => -1,
+        }
+    });
+
+    match result {
+        Ok(id) => id,
+        Err(_) => -1,
+    }
+}
+
 #[cfg(feature = "android-jni")]
 #[no_mangle]
 pub extern "system" fn Java_com_example_whisper_WhisperEngine_freeWhisper(
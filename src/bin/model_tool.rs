@@ -0,0 +1,48 @@
+//! whisper-model-tool: check and quantize model files from the command line
+
+use std::env;
+use std::process;
+use whisper_rust_binding::{quantize_model, ModelFile, QuantizationType};
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage:");
+    eprintln!("  {} inspect <model_file>", program);
+    eprintln!("  {} quantize <input_model> <output_model> <q5_0|q5_1|q8_0>", program);
+    process::exit(2);
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("inspect") if args.len() == 3 => {
+            let model = ModelFile::inspect(&args[2])?;
+            println!("Model: {}", args[2]);
+            println!("  Type: {} ({})", model.hparams.model_type(),
+                if model.hparams.is_multilingual() { "multilingual" } else { "English-only" });
+            println!("  ftype: {}", model.hparams.ftype);
+            println!("  Tensors: {}", model.n_tensors);
+            println!("  Size: {:.1} MB", mb(model.file_size));
+        }
+        Some("quantize") if args.len() == 5 => {
+            let quantization: QuantizationType = args[4].parse()?;
+            println!("Quantizing {} to {}...", args[2], quantization);
+
+            let report = quantize_model(&args[2], &args[3], quantization)?;
+            println!("Wrote {}", args[3]);
+            println!("  Tensors: {} quantized, {} copied", report.quantized_tensors, report.copied_tensors);
+            println!("  Size: {:.1} MB -> {:.1} MB ({:.0}%)",
+                mb(report.input_size),
+                mb(report.output_size),
+                report.output_size as f64 * 100.0 / report.input_size as f64);
+        }
+        _ => usage(args.first().map_or("whisper-model-tool", String::as_str)),
+    }
+
+    Ok(())
+}
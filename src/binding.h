@@ -2,11 +2,46 @@
 #define WHISPER_RUST_BINDING_H
 
 #include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
 
 #ifdef __cplusplus
 extern "C" {
 #endif
 
+/**
+ * Error codes returned by whisper_rust_last_error_code. Values are stable.
+ */
+#define WHISPER_RUST_OK                    0
+#define WHISPER_RUST_ERR_MODEL_INIT        1
+#define WHISPER_RUST_ERR_INVALID_MODEL     2
+#define WHISPER_RUST_ERR_PROCESSING        3
+#define WHISPER_RUST_ERR_INVALID_PARAMETER 4
+#define WHISPER_RUST_ERR_INVALID_AUDIO     5
+#define WHISPER_RUST_ERR_INTERNAL          6
+#define WHISPER_RUST_ERR_CANCELLED         7
+#define WHISPER_RUST_ERR_DEADLINE_EXCEEDED 8
+#define WHISPER_RUST_ERR_BUFFER_TOO_SMALL  9
+#define WHISPER_RUST_ERR_TRUNCATED_MODEL   10
+#define WHISPER_RUST_ERR_UNSUPPORTED_MODEL 11
+#define WHISPER_RUST_ERR_CHECKSUM_MISMATCH 12
+
+/**
+ * Error code of the last failed call on the calling thread.
+ * Every fallible function sets it on failure and resets it on success.
+ * A panic inside the library is reported as WHISPER_RUST_ERR_INTERNAL.
+ * @return One of the WHISPER_RUST_* codes, WHISPER_RUST_OK if the last call succeeded
+ */
+int whisper_rust_last_error_code(void);
+
+/**
+ * Message describing the last failed call on the calling thread.
+ * For WHISPER_RUST_ERR_BUFFER_TOO_SMALL it includes the size needed.
+ * @return A string owned by the library, valid until the next call on this
+ *         thread, or NULL if the last call succeeded. Do not free it.
+ */
+const char* whisper_rust_last_error_message(void);
+
 /**
  * Initialize a Whisper model.
  * @param model_path Path to the model file (.bin)
@@ -14,6 +49,48 @@ extern "C" {
  */
 int whisper_rust_init(const char* model_path);
 
+/**
+ * Initialize a Whisper model with context options.
+ * @param model_path Path to the model file (.bin)
+ * @param options_json JSON object with any of "use_gpu", "flash_attn",
+ *        "gpu_device", "dtw_token_timestamps" and "dtw_aheads_preset"
+ *        (e.g. "Tiny", "BaseEn"; null picks the preset matching the model),
+ *        or NULL for defaults
+ * @return A positive instance ID on success, -1 on failure
+ */
+int whisper_rust_init_with_options(const char* model_path, const char* options_json);
+
+/**
+ * Initialize a Whisper model from memory, e.g. a bundled asset or a decrypted download.
+ * @param buffer The model file contents; only read during the call
+ * @param buffer_size Size of buffer in bytes
+ * @param options_json Context options as for whisper_rust_init_with_options, or NULL
+ * @return A positive instance ID on success, -1 on failure
+ */
+int whisper_rust_init_from_buffer(const void* buffer, size_t buffer_size, const char* options_json);
+
+/**
+ * Initialize a Whisper model stored in an open file (POSIX only), e.g. an
+ * Android AssetFileDescriptor. The descriptor remains owned by the caller,
+ * its file position is not changed, and it may be closed once this returns.
+ * @param fd An open, readable file descriptor
+ * @param offset Byte offset of the model within the file
+ * @param options_json Context options as for whisper_rust_init_with_options, or NULL
+ * @return A positive instance ID on success, -1 on failure
+ */
+int whisper_rust_init_from_fd(int fd, int64_t offset, const char* options_json);
+
+/**
+ * Initialize a model by name from a models directory. The directory's
+ * models.json manifest maps names (e.g. "tiny-ar") to files and SHA-256
+ * checksums; the file is checked for truncation and checksum before loading.
+ * @param models_dir Directory containing the models and models.json
+ * @param model_name Name from the manifest, or a file name without "ggml-" and ".bin"
+ * @param options_json Context options as for whisper_rust_init_with_options, or NULL
+ * @return A positive instance ID on success, -1 on failure
+ */
+int whisper_rust_init_from_registry(const char* models_dir, const char* model_name, const char* options_json);
+
 /**
  * Free resources associated with a Whisper instance.
  * @param instance_id The instance ID returned from whisper_rust_init
@@ -21,6 +98,16 @@ int whisper_rust_init(const char* model_path);
  */
 bool whisper_rust_free(int instance_id);
 
+/**
+ * Abort the transcription currently running on an instance (safe to call from
+ * another thread). The running call returns false; whisper_rust_process_audio_segments
+ * and whisper_rust_process_audio_with_options still write the segments completed
+ * before the abort to their result buffer. Later calls run normally.
+ * @param instance_id The instance ID returned from whisper_rust_init
+ * @return true if the instance exists, false otherwise
+ */
+bool whisper_rust_cancel(int instance_id);
+
 /**
  * Check if a Whisper model is valid.
  * @param instance_id The instance ID returned from whisper_rust_init
@@ -72,6 +159,80 @@ bool whisper_rust_process_audio_sliding_window(
     int result_buffer_size
 );
 
+/**
+ * Process audio data and return timestamped segments.
+ * The result is a JSON array of objects with the fields
+ * "text", "t0_ms", "t1_ms", "no_speech_prob" and "tokens". Each token is an
+ * object with "id", "text", "p", "plog", "t0_ms", "t1_ms", "t_dtw_ms" (null
+ * unless DTW is enabled) and "special".
+ * @param instance_id The instance ID returned from whisper_rust_init
+ * @param audio_data Pointer to audio data (32-bit float PCM, 16kHz mono)
+ * @param audio_len Length of audio data in number of samples
+ * @param language Language code (e.g., "en", "ar", etc.) or NULL for auto-detection
+ * @param result_buffer Buffer to store the JSON result
+ * @param result_buffer_size Size of the result buffer
+ * @return true on success, false on failure
+ */
+bool whisper_rust_process_audio_segments(
+    int instance_id,
+    const float* audio_data,
+    int audio_len,
+    const char* language,
+    char* result_buffer,
+    int result_buffer_size
+);
+
+/**
+ * Process audio data with explicit decoding options.
+ * @param instance_id The instance ID returned from whisper_rust_init
+ * @param audio_data Pointer to audio data (32-bit float PCM, 16kHz mono)
+ * @param audio_len Length of audio data in number of samples
+ * @param options_json JSON object with any TranscribeOptions field, e.g.
+ *        {"language": "ar", "strategy": {"BeamSearch": {"beam_size": 5, "patience": -1.0}},
+ *         "temperature_inc": 0.0, "single_segment": true,
+ *         "initial_prompt": "...", "prompt_tokens": [50364, ...],
+ *         "allowed_languages": ["ar", "en"],
+ *         "grammar": "root ::= \" yes\" | \" no\"", "grammar_rule": "root",
+ *         "grammar_penalty": 100.0, "timeout_ms": 5000,
+ *         "vad": {"model_path": "ggml-silero-v5.1.2.bin", "threshold": 0.5,
+ *                 "min_speech_duration_ms": 250, "min_silence_duration_ms": 2000,
+ *                 "speech_pad_ms": 30}}; NULL for defaults.
+ *        Non-empty "prompt_tokens" take precedence over "initial_prompt".
+ *        "grammar" is GBNF text; an invalid grammar makes the call fail.
+ *        When "timeout_ms" expires the call returns false with the segments
+ *        completed so far in result_buffer.
+ * @param result_buffer Buffer to store the JSON segments (same format as
+ *        whisper_rust_process_audio_segments)
+ * @param result_buffer_size Size of the result buffer
+ * @return true on success, false on failure
+ */
+bool whisper_rust_process_audio_with_options(
+    int instance_id,
+    const float* audio_data,
+    int audio_len,
+    const char* options_json,
+    char* result_buffer,
+    int result_buffer_size
+);
+
+/**
+ * Detect the spoken language of the audio.
+ * @param instance_id The instance ID returned from whisper_rust_init
+ * @param audio_data Pointer to audio data (32-bit float PCM, 16kHz mono)
+ * @param audio_len Length of audio data in number of samples
+ * @param result_buffer Buffer to store a JSON array of
+ *        {"language": "ar", "probability": 0.93} objects, most likely first
+ * @param result_buffer_size Size of the result buffer
+ * @return true on success, false on failure (e.g. English-only model)
+ */
+bool whisper_rust_detect_language(
+    int instance_id,
+    const float* audio_data,
+    int audio_len,
+    char* result_buffer,
+    int result_buffer_size
+);
+
 /**
  * Validate if a word exists in the global data words array.
  * @param word The word to validate
@@ -86,7 +247,11 @@ bool whisper_rust_validate_word(
 );
 
 /**
- * Get information about the loaded model.
+ * Get information about the loaded model as a JSON object with "model_type",
+ * "multilingual", "n_vocab", "n_audio_ctx", "n_audio_state", "n_audio_head",
+ * "n_audio_layer", "n_text_ctx", "n_text_state", "n_text_head", "n_text_layer",
+ * "n_mels", "ftype", "quantization", "file_size" (null if unknown), "languages"
+ * and "model_path". Multilingual models list ~100 languages, so allow a few KB.
  * @param instance_id The instance ID returned from whisper_rust_init
  * @param info_buffer Buffer to store the model information
  * @param info_buffer_size Size of the info buffer
@@ -98,6 +263,80 @@ bool whisper_rust_get_model_info(
     int info_buffer_size
 );
 
+/*
+ * Library-allocated results.
+ *
+ * The buffer functions above fail when the result does not fit the buffer. The
+ * _alloc variants below take the same arguments minus the buffer and return a
+ * NUL-terminated string of any length, or NULL on failure. The string is owned
+ * by the caller and must be released with whisper_rust_free_string.
+ */
+
+/** Transcribed text, like whisper_rust_process_audio. */
+char* whisper_rust_process_audio_alloc(
+    int instance_id,
+    const float* audio_data,
+    int audio_len,
+    const char* language
+);
+
+/** Transcribed text, like whisper_rust_process_audio_sliding_window. */
+char* whisper_rust_process_audio_sliding_window_alloc(
+    int instance_id,
+    const float* audio_data,
+    int audio_len,
+    float window_size_sec,
+    float step_size_sec,
+    int sample_rate,
+    const char* language
+);
+
+/**
+ * JSON segments, like whisper_rust_process_audio_segments.
+ * A cancelled or timed-out call returns NULL; use the buffer variant to
+ * receive the segments completed before the abort.
+ */
+char* whisper_rust_process_audio_segments_alloc(
+    int instance_id,
+    const float* audio_data,
+    int audio_len,
+    const char* language
+);
+
+/** JSON segments, like whisper_rust_process_audio_with_options. */
+char* whisper_rust_process_audio_with_options_alloc(
+    int instance_id,
+    const float* audio_data,
+    int audio_len,
+    const char* options_json
+);
+
+/** JSON language probabilities, like whisper_rust_detect_language. */
+char* whisper_rust_detect_language_alloc(
+    int instance_id,
+    const float* audio_data,
+    int audio_len
+);
+
+/** Model information, like whisper_rust_get_model_info. */
+char* whisper_rust_get_model_info_alloc(int instance_id);
+
+/**
+ * Check a model file is a complete ggml whisper model without loading it.
+ * @param model_path Path to the model file (.bin)
+ * @return JSON with "hparams", "n_mel_filters", "n_fft", "n_tokens", "n_tensors"
+ *         and "file_size", or NULL with WHISPER_RUST_ERR_TRUNCATED_MODEL,
+ *         WHISPER_RUST_ERR_UNSUPPORTED_MODEL (e.g. GGUF) or
+ *         WHISPER_RUST_ERR_INVALID_MODEL in the last error
+ */
+char* whisper_rust_inspect_model_alloc(const char* model_path);
+
+/**
+ * Release a string returned by an _alloc function. NULL is ignored.
+ * @param value The string to free
+ */
+void whisper_rust_free_string(char* value);
+
 #ifdef __cplusplus
 }
 #endif
@@ -0,0 +1,101 @@
+//! Trampolines forwarding whisper.cpp progress/new-segment callbacks to Rust closures
+
+use std::any::Any;
+use std::ffi::{c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use crate::{read_segment, ffi, Segment};
+
+/// User data behind `progress_callback` and `new_segment_callback`.
+/// Both are invoked on the thread running `whisper_full_with_state`.
+pub(crate) struct Callbacks<'a> {
+    on_progress: &'a mut dyn FnMut(u8),
+    on_segment: &'a mut dyn FnMut(&Segment),
+    // A panic must not unwind through whisper.cpp; it is re-raised once the call returns
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl<'a> Callbacks<'a> {
+    pub(crate) fn new(on_progress: &'a mut dyn FnMut(u8), on_segment: &'a mut dyn FnMut(&Segment)) -> Self {
+        Self {
+            on_progress,
+            on_segment,
+            panic: None,
+        }
+    }
+
+    /// Re-raise a panic caught inside a callback
+    pub(crate) fn resume_panic(&mut self) {
+        if let Some(payload) = self.panic.take() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn guard(&mut self, f: impl FnOnce(&mut Self)) {
+        // After a panic the closures may be in a broken state; stop calling them
+        if self.panic.is_some() {
+            return;
+        }
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            self.panic = Some(payload);
+        }
+    }
+}
+
+/// `whisper_progress_callback`
+pub(crate) unsafe extern "C" fn progress_callback(
+    _ctx: *mut c_void,
+    _state: *mut c_void,
+    progress: c_int,
+    user_data: *mut c_void,
+) {
+    if let Some(callbacks) = unsafe { (user_data as *mut Callbacks).as_mut() } {
+        callbacks.guard(|callbacks| (callbacks.on_progress)(progress.clamp(0, 100) as u8));
+    }
+}
+
+/// `whisper_new_segment_callback`: the last `n_new` segments of `state` are new
+pub(crate) unsafe extern "C" fn new_segment_callback(
+    ctx: *mut c_void,
+    state: *mut c_void,
+    n_new: c_int,
+    user_data: *mut c_void,
+) {
+    if let Some(callbacks) = unsafe { (user_data as *mut Callbacks).as_mut() } {
+        callbacks.guard(|callbacks| {
+            let n_segments = unsafe { ffi::whisper_full_n_segments_from_state(state) };
+            for i in (n_segments - n_new).max(0)..n_segments {
+                if let Some(segment) = read_segment(ctx, state, i) {
+                    (callbacks.on_segment)(&segment);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_is_caught_and_resumed() {
+        let mut calls = Vec::new();
+        let mut on_progress = |p: u8| {
+            calls.push(p);
+            if p == 50 {
+                panic!("progress bar went away");
+            }
+        };
+        let mut on_segment = |_: &Segment| {};
+        let mut callbacks = Callbacks::new(&mut on_progress, &mut on_segment);
+        let user_data = &mut callbacks as *mut Callbacks as *mut c_void;
+
+        for progress in [10, 50, 90, 150] {
+            unsafe { progress_callback(std::ptr::null_mut(), std::ptr::null_mut(), progress, user_data) };
+        }
+
+        let resumed = panic::catch_unwind(AssertUnwindSafe(|| callbacks.resume_panic()));
+        assert!(resumed.is_err());
+        drop(callbacks);
+        assert_eq!(calls, vec![10, 50]);
+    }
+}
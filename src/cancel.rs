@@ -0,0 +1,134 @@
+//! Cooperative cancellation of running transcriptions
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Shared flag that stops a running transcription at the next abort check.
+/// Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Clear the flag so the token can be reused for the next call
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+}
+
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+/// Why a transcription was stopped early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AbortReason {
+    Cancelled = 1,
+    DeadlineExceeded = 2,
+}
+
+/// User data behind `abort_callback` and `encoder_begin_callback`.
+/// whisper.cpp may poll it from its worker threads, hence the atomics.
+pub(crate) struct AbortSignal {
+    tokens: Vec<CancellationToken>,
+    deadline: Option<Instant>,
+    reason: AtomicU8,
+}
+
+impl AbortSignal {
+    pub(crate) fn new(tokens: Vec<CancellationToken>, deadline: Option<Instant>) -> Self {
+        Self {
+            tokens,
+            deadline,
+            reason: AtomicU8::new(0),
+        }
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        !self.tokens.is_empty() || self.deadline.is_some()
+    }
+
+    /// Check the tokens and deadline, remembering the first reason found
+    pub(crate) fn should_abort(&self) -> bool {
+        if self.reason().is_some() {
+            return true;
+        }
+
+        let reason = if self.tokens.iter().any(CancellationToken::is_cancelled) {
+            AbortReason::Cancelled
+        } else if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            AbortReason::DeadlineExceeded
+        } else {
+            return false;
+        };
+
+        let _ = self.reason.compare_exchange(0, reason as u8, Ordering::SeqCst, Ordering::SeqCst);
+        true
+    }
+
+    pub(crate) fn reason(&self) -> Option<AbortReason> {
+        match self.reason.load(Ordering::SeqCst) {
+            1 => Some(AbortReason::Cancelled),
+            2 => Some(AbortReason::DeadlineExceeded),
+            _ => None,
+        }
+    }
+}
+
+/// `ggml_abort_callback`: return true to abort
+pub(crate) unsafe extern "C" fn abort_callback(user_data: *mut c_void) -> bool {
+    match unsafe { (user_data as *const AbortSignal).as_ref() } {
+        Some(signal) => signal.should_abort(),
+        None => false,
+    }
+}
+
+/// `whisper_encoder_begin_callback`: return false to skip the encoder run
+pub(crate) unsafe extern "C" fn encoder_begin_callback(
+    _ctx: *mut c_void,
+    _state: *mut c_void,
+    user_data: *mut c_void,
+) -> bool {
+    match unsafe { (user_data as *const AbortSignal).as_ref() } {
+        Some(signal) => !signal.should_abort(),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_signal_reports_first_reason() {
+        let token = CancellationToken::new();
+        let signal = AbortSignal::new(vec![token.clone()], Some(Instant::now() + Duration::from_secs(60)));
+        assert!(!signal.should_abort());
+
+        token.cancel();
+        assert!(signal.should_abort());
+        assert_eq!(signal.reason(), Some(AbortReason::Cancelled));
+
+        let expired = AbortSignal::new(Vec::new(), Some(Instant::now()));
+        assert!(unsafe { !encoder_begin_callback(std::ptr::null_mut(), std::ptr::null_mut(), &expired as *const _ as *mut c_void) });
+        assert_eq!(expired.reason(), Some(AbortReason::DeadlineExceeded));
+    }
+}
@@ -0,0 +1,159 @@
+//! Numeric error codes and the per-thread last error reported over the C API
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::panic::{self, AssertUnwindSafe};
+use crate::WhisperError;
+
+/// Stable error codes for native callers. Values are never reused or renumbered.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    ModelInit = 1,
+    InvalidModel = 2,
+    Processing = 3,
+    InvalidParameter = 4,
+    InvalidAudioData = 5,
+    Internal = 6,
+    Cancelled = 7,
+    DeadlineExceeded = 8,
+    BufferTooSmall = 9,
+    TruncatedModel = 10,
+    UnsupportedModelFormat = 11,
+    ChecksumMismatch = 12,
+}
+
+impl WhisperError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            WhisperError::ModelInitError(_) => ErrorCode::ModelInit,
+            WhisperError::InvalidModel(_) => ErrorCode::InvalidModel,
+            WhisperError::ProcessingError(_) => ErrorCode::Processing,
+            WhisperError::InvalidParameter(_) => ErrorCode::InvalidParameter,
+            WhisperError::InvalidAudioData => ErrorCode::InvalidAudioData,
+            WhisperError::InternalError(_) => ErrorCode::Internal,
+            WhisperError::Cancelled(_) => ErrorCode::Cancelled,
+            WhisperError::DeadlineExceeded(_) => ErrorCode::DeadlineExceeded,
+            WhisperError::BufferTooSmall(_) => ErrorCode::BufferTooSmall,
+            WhisperError::TruncatedModel(_) => ErrorCode::TruncatedModel,
+            WhisperError::UnsupportedModelFormat(_) => ErrorCode::UnsupportedModelFormat,
+            WhisperError::ChecksumMismatch(_) => ErrorCode::ChecksumMismatch,
+        }
+    }
+}
+
+struct LastError {
+    code: ErrorCode,
+    message: CString,
+}
+
+thread_local! {
+    // Each thread sees the outcome of its own most recent call
+    static LAST_ERROR: RefCell<Option<LastError>> = const { RefCell::new(None) };
+}
+
+/// Run the body of an exported function and record its outcome.
+/// A panic is reported as an `Internal` error instead of unwinding into C.
+pub(crate) fn guard<T>(f: impl FnOnce() -> Result<T, WhisperError>) -> Option<T> {
+    catch_panic(None, || report(f()))
+}
+
+/// Run the body of an export that has no error to report; a panic returns `fallback`
+pub(crate) fn catch_panic<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        set_last_error(&WhisperError::InternalError(format!("Panic: {}", panic_message(payload.as_ref()))));
+        fallback
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Record the outcome of an exported call: errors are kept, success clears them
+pub(crate) fn report<T>(result: Result<T, WhisperError>) -> Option<T> {
+    match result {
+        Ok(value) => {
+            clear_last_error();
+            Some(value)
+        }
+        Err(e) => {
+            set_last_error(&e);
+            None
+        }
+    }
+}
+
+pub(crate) fn set_last_error(error: &WhisperError) {
+    // Messages built from C strings can't contain NUL, but don't lose the error if one does
+    let message = CString::new(error.to_string().replace('\0', " ")).unwrap_or_default();
+    // try_with: these run inside the panic guard, so they must not panic during thread teardown
+    let _ = LAST_ERROR.try_with(|last| *last.borrow_mut() = Some(LastError { code: error.code(), message }));
+}
+
+pub(crate) fn clear_last_error() {
+    let _ = LAST_ERROR.try_with(|last| *last.borrow_mut() = None);
+}
+
+pub(crate) fn last_error_code() -> ErrorCode {
+    LAST_ERROR
+        .try_with(|last| last.borrow().as_ref().map_or(ErrorCode::Ok, |error| error.code))
+        .unwrap_or(ErrorCode::Ok)
+}
+
+/// Pointer to the last error message, or NULL. It stays valid until the next
+/// exported call on this thread.
+pub(crate) fn last_error_message() -> *const std::ffi::c_char {
+    LAST_ERROR
+        .try_with(|last| {
+            last.borrow()
+                .as_ref()
+                .map_or(std::ptr::null(), |error| error.message.as_ptr())
+        })
+        .unwrap_or(std::ptr::null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_last_error_is_per_call_and_per_thread() {
+        assert_eq!(report::<()>(Err(WhisperError::BufferTooSmall(42))), None);
+        assert_eq!(last_error_code(), ErrorCode::BufferTooSmall);
+        let message = unsafe { CStr::from_ptr(last_error_message()) }.to_str().unwrap();
+        assert!(message.contains("42"));
+
+        std::thread::spawn(|| assert_eq!(last_error_code(), ErrorCode::Ok)).join().unwrap();
+
+        assert_eq!(report(Ok(1)), Some(1));
+        assert_eq!(last_error_code(), ErrorCode::Ok);
+        assert!(last_error_message().is_null());
+    }
+
+    #[test]
+    fn test_panic_becomes_internal_error() {
+        let result: Option<()> = guard(|| panic!("index out of range"));
+        assert_eq!(result, None);
+        assert_eq!(last_error_code(), ErrorCode::Internal);
+        let message = unsafe { CStr::from_ptr(last_error_message()) }.to_str().unwrap();
+        assert!(message.contains("index out of range"));
+
+        let value = catch_panic(-1, || panic!("{}", String::from("formatted")));
+        assert_eq!(value, -1);
+        let message = unsafe { CStr::from_ptr(last_error_message()) }.to_str().unwrap();
+        assert!(message.contains("formatted"));
+
+        assert_eq!(guard(|| Ok(3)), Some(3));
+        assert_eq!(last_error_code(), ErrorCode::Ok);
+    }
+}
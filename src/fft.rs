@@ -0,0 +1,161 @@
+//! In-place iterative FFT shared by the mel front-end and the VAD
+//!
+//! Lengths are `2^k * m` with `m` odd: the input is reordered into `2^k`
+//! interleaved subsequences of `m` samples, each gets a plain DFT, and radix-2
+//! butterflies combine them. Powers of two (`m = 1`) are a regular radix-2 FFT;
+//! whisper.cpp's 400-point transform is 16 DFTs of 25 points.
+
+use std::f32::consts::PI;
+
+/// A transform plan for one length; reuse it across frames
+pub(crate) struct Fft {
+    n: usize,
+    /// Odd length of the subsequences that get a plain DFT
+    odd_len: usize,
+    /// cos and sin of `2 pi k / n`
+    cos: Vec<f32>,
+    sin: Vec<f32>,
+    /// Input index of each position once reordered into subsequences
+    order: Vec<usize>,
+    scratch_re: Vec<f32>,
+    scratch_im: Vec<f32>,
+}
+
+impl Fft {
+    pub(crate) fn new(n: usize) -> Self {
+        let n = n.max(1);
+        let n_sub = 1 << n.trailing_zeros();
+        let odd_len = n / n_sub;
+        let bits = n_sub.trailing_zeros();
+
+        // Subsequence j holds the samples r, r + n_sub, r + 2 n_sub, ...
+        // where r is j with its bits reversed
+        let order = (0..n)
+            .map(|position| {
+                let (j, t) = (position / odd_len, position % odd_len);
+                let r = if bits == 0 { 0 } else { j.reverse_bits() >> (usize::BITS - bits) };
+                r + t * n_sub
+            })
+            .collect();
+
+        let (sin, cos) = (0..n).map(|k| (2.0 * PI * k as f32 / n as f32).sin_cos()).unzip();
+
+        Self {
+            n,
+            odd_len,
+            cos,
+            sin,
+            order,
+            scratch_re: vec![0.0; n],
+            scratch_im: vec![0.0; n],
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.n
+    }
+
+    /// Replace `re` + i `im` (both `len()` long) with its spectrum
+    pub(crate) fn process(&mut self, re: &mut [f32], im: &mut [f32]) {
+        let n = self.n;
+        assert!(re.len() == n && im.len() == n, "FFT of {} points given {} values", n, re.len());
+
+        for (position, &index) in self.order.iter().enumerate() {
+            self.scratch_re[position] = re[index];
+            self.scratch_im[position] = im[index];
+        }
+
+        // Plain DFT of each subsequence, written back in place
+        let m = self.odd_len;
+        let step = n / m;
+        for start in (0..n).step_by(m) {
+            let (sub_re, sub_im) = (&self.scratch_re[start..start + m], &self.scratch_im[start..start + m]);
+            for f in 0..m {
+                let (mut sum_re, mut sum_im) = (0.0, 0.0);
+                for t in 0..m {
+                    let index = (f * t * step) % n;
+                    let (cos, sin) = (self.cos[index], self.sin[index]);
+                    sum_re += sub_re[t] * cos + sub_im[t] * sin;
+                    sum_im += sub_im[t] * cos - sub_re[t] * sin;
+                }
+                re[start + f] = sum_re;
+                im[start + f] = sum_im;
+            }
+        }
+
+        // Radix-2 butterflies, doubling the transform length each pass
+        let mut size = 2 * m;
+        while size <= n {
+            let half = size / 2;
+            let step = n / size;
+            for start in (0..n).step_by(size) {
+                for k in 0..half {
+                    let (cos, sin) = (self.cos[k * step], self.sin[k * step]);
+                    let a = start + k;
+                    let b = a + half;
+                    let tr = re[b] * cos + im[b] * sin;
+                    let ti = im[b] * cos - re[b] * sin;
+                    re[b] = re[a] - tr;
+                    im[b] = im[a] - ti;
+                    re[a] += tr;
+                    im[a] += ti;
+                }
+            }
+            size *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Direct O(n^2) DFT in f64
+    fn dft(re: &[f32], im: &[f32]) -> Vec<(f64, f64)> {
+        let n = re.len();
+        (0..n)
+            .map(|k| {
+                (0..n).fold((0.0, 0.0), |(sum_re, sum_im), t| {
+                    let angle = -2.0 * std::f64::consts::PI * (k * t % n) as f64 / n as f64;
+                    let (sin, cos) = angle.sin_cos();
+                    let (x_re, x_im) = (re[t] as f64, im[t] as f64);
+                    (sum_re + x_re * cos - x_im * sin, sum_im + x_re * sin + x_im * cos)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fft_finds_tone_bin() {
+        let n = 64;
+        let mut re: Vec<f32> = (0..n).map(|i| (2.0 * PI * 8.0 * i as f32 / n as f32).cos()).collect();
+        let mut im = vec![0.0; n];
+        Fft::new(n).process(&mut re, &mut im);
+
+        let peak = (0..n / 2)
+            .max_by(|&a, &b| (re[a].hypot(im[a])).total_cmp(&re[b].hypot(im[b])))
+            .unwrap();
+        assert_eq!(peak, 8);
+        assert!((re[8] - n as f32 / 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_matches_dft_for_mixed_lengths() {
+        for n in [1, 2, 5, 12, 64, 400] {
+            let re: Vec<f32> = (0..n).map(|i| ((i * 7 + 3) % 11) as f32 - 5.0).collect();
+            let im: Vec<f32> = (0..n).map(|i| ((i * 5 + 1) % 7) as f32 * 0.5).collect();
+            let expected = dft(&re, &im);
+
+            let mut fft = Fft::new(n);
+            assert_eq!(fft.len(), n);
+            let (mut out_re, mut out_im) = (re.clone(), im.clone());
+            fft.process(&mut out_re, &mut out_im);
+
+            for (k, &(want_re, want_im)) in expected.iter().enumerate() {
+                let tolerance = 1e-4 * n as f64;
+                assert!((out_re[k] as f64 - want_re).abs() < tolerance, "n {} bin {} re", n, k);
+                assert!((out_im[k] as f64 - want_im).abs() < tolerance, "n {} bin {} im", n, k);
+            }
+        }
+    }
+}
@@ -1,10 +1,16 @@
 use crate::flutter_transcriber::*;
-use std::sync::{Arc, Mutex};
+use crate::{log_mel_spectrogram_with, ModelFile, ModelRegistry, TranscribeOptions};
+use std::sync::Arc;
+use parking_lot::Mutex;
 use std::collections::HashMap;
 use once_cell::sync::Lazy;
 
-// Global transcriber instances management
-static TRANSCRIBER_INSTANCES: Lazy<Arc<Mutex<HashMap<String, FlutterTranscriber>>>> = 
+// Global transcriber instances management. Transcribers are shared so long calls
+// (processing, language detection) run without holding the map lock, which keeps
+// cancel() and the other calls responsive.
+type TranscriberMap = HashMap<String, Arc<FlutterTranscriber>>;
+
+static TRANSCRIBER_INSTANCES: Lazy<Arc<Mutex<TranscriberMap>>> = 
     Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
 
 /// Flutter Rust Bridge API for production-ready real-time transcription
@@ -48,6 +54,45 @@ pub struct FrbProcessingStats {
     pub average_processing_time_ms: f64,
     pub real_time_factor: f64,
     pub buffer_overflows: u64,
+    pub skipped_silent_windows: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrbLanguageProbability {
+    pub language: String,
+    pub probability: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrbModelInfo {
+    pub model_type: String,
+    pub multilingual: bool,
+    pub n_vocab: i32,
+    pub n_audio_layer: i32,
+    pub n_audio_head: i32,
+    pub n_text_layer: i32,
+    pub n_text_head: i32,
+    pub n_mels: i32,
+    pub quantization: String,
+    pub file_size_bytes: Option<u64>,
+    pub languages: Vec<String>,
+}
+
+/// Log-mel spectrogram for display: `n_mel` rows of `n_frames` values (10 ms each)
+#[derive(Debug, Clone)]
+pub struct FrbMelSpectrogram {
+    pub n_mel: u32,
+    pub n_frames: u32,
+    pub data: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrbModelEntry {
+    pub name: String,
+    pub file: String,
+    pub size: Option<String>,
+    pub languages: Vec<String>,
+    pub has_checksum: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +103,9 @@ pub struct FrbTranscriberConfig {
     pub window_duration_ms: u32,
     pub overlap_duration_ms: u32,
     pub chunk_size_ms: u32,
+    pub options: TranscribeOptions,
+    /// Skip windows the built-in voice activity detector finds silent
+    pub skip_silence: bool,
 }
 
 impl Default for FrbTranscriberConfig {
@@ -69,6 +117,8 @@ impl Default for FrbTranscriberConfig {
             window_duration_ms: 2000,
             overlap_duration_ms: 500,
             chunk_size_ms: 50,
+            options: TranscribeOptions::default(),
+            skip_silence: true,
         }
     }
 }
@@ -88,8 +138,12 @@ impl FlutterTranscriberApi {
             config.chunk_size_ms,
         ) {
             Ok(transcriber) => {
-                let mut instances = TRANSCRIBER_INSTANCES.lock().unwrap();
-                instances.insert(instance_id.clone(), transcriber);
+                let mut transcriber = transcriber.with_transcribe_options(config.options);
+                if !config.skip_silence {
+                    transcriber = transcriber.with_speech_gate(None);
+                }
+                let mut instances = TRANSCRIBER_INSTANCES.lock();
+                instances.insert(instance_id.clone(), Arc::new(transcriber));
                 Ok(format!("✅ Transcriber '{}' created successfully", instance_id))
             }
             Err(e) => Err(format!("❌ Failed to create transcriber: {}", e)),
@@ -101,7 +155,7 @@ impl FlutterTranscriberApi {
         instance_id: String,
         audio_data: Vec<f32>,
     ) -> Result<FrbBufferStatus, String> {
-        let instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+        let instances = TRANSCRIBER_INSTANCES.lock();
         
         if let Some(transcriber) = instances.get(&instance_id) {
             match transcriber.add_audio_chunk(&audio_data) {
@@ -120,9 +174,9 @@ impl FlutterTranscriberApi {
     
     /// Process audio if ready and return transcription
     pub fn process_if_ready(instance_id: String) -> Result<Option<FrbTranscriptionResult>, String> {
-        let instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+        let transcriber = TRANSCRIBER_INSTANCES.lock().get(&instance_id).cloned();
         
-        if let Some(transcriber) = instances.get(&instance_id) {
+        if let Some(transcriber) = transcriber {
             match transcriber.process_if_ready() {
                 Ok(Some(result)) => Ok(Some(FrbTranscriptionResult {
                     text: result.text,
@@ -147,7 +201,7 @@ impl FlutterTranscriberApi {
         transcribed_text: String,
         expected_text: String,
     ) -> Result<FrbValidationResult, String> {
-        let instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+        let instances = TRANSCRIBER_INSTANCES.lock();
         
         if let Some(transcriber) = instances.get(&instance_id) {
             let result = transcriber.validate_transcription(&transcribed_text, &expected_text);
@@ -165,9 +219,134 @@ impl FlutterTranscriberApi {
         }
     }
     
+    /// Set (or clear with `None`) the text used to bias decoding, e.g. the expected ayah
+    pub fn set_initial_prompt(instance_id: String, prompt: Option<String>) -> Result<(), String> {
+        let instances = TRANSCRIBER_INSTANCES.lock();
+        
+        if let Some(transcriber) = instances.get(&instance_id) {
+            transcriber.set_initial_prompt(prompt);
+            Ok(())
+        } else {
+            Err(format!("❌ Transcriber instance '{}' not found", instance_id))
+        }
+    }
+    
+    /// Abort the window currently being transcribed (e.g. when the user leaves the screen).
+    /// The pending `process_if_ready` call returns the segments completed before the abort.
+    pub fn cancel(instance_id: String) -> Result<(), String> {
+        let instances = TRANSCRIBER_INSTANCES.lock();
+        
+        if let Some(transcriber) = instances.get(&instance_id) {
+            transcriber.cancel();
+            Ok(())
+        } else {
+            Err(format!("❌ Transcriber instance '{}' not found", instance_id))
+        }
+    }
+    
+    /// Detect the spoken language (e.g. to reject non-Arabic input), most likely first
+    pub fn detect_language(
+        instance_id: String,
+        audio_data: Vec<f32>,
+    ) -> Result<Vec<FrbLanguageProbability>, String> {
+        let transcriber = TRANSCRIBER_INSTANCES.lock().get(&instance_id).cloned();
+        
+        if let Some(transcriber) = transcriber {
+            match transcriber.detect_language(&audio_data) {
+                Ok(languages) => Ok(languages
+                    .into_iter()
+                    .map(|(language, probability)| FrbLanguageProbability { language, probability })
+                    .collect()),
+                Err(e) => Err(format!("❌ Language detection failed: {}", e)),
+            }
+        } else {
+            Err(format!("❌ Transcriber instance '{}' not found", instance_id))
+        }
+    }
+    
+    /// Log-mel spectrogram of audio as the transcriber's model sees it, e.g. to draw it
+    pub fn compute_mel(instance_id: String, audio_data: Vec<f32>) -> Result<FrbMelSpectrogram, String> {
+        let mel_filters = match TRANSCRIBER_INSTANCES.lock().get(&instance_id) {
+            Some(transcriber) => transcriber.mel_filters().clone(),
+            None => return Err(format!("❌ Transcriber instance '{}' not found", instance_id)),
+        };
+        
+        match log_mel_spectrogram_with(&audio_data, &mel_filters) {
+            Ok(mel) => Ok(FrbMelSpectrogram {
+                n_mel: mel.n_mel as u32,
+                n_frames: mel.n_frames as u32,
+                data: mel.data,
+            }),
+            Err(e) => Err(format!("❌ Mel spectrogram failed: {}", e)),
+        }
+    }
+    
+    /// List the models in a models directory (manifest entries first)
+    pub fn list_models(models_dir: String) -> Result<Vec<FrbModelEntry>, String> {
+        let registry = ModelRegistry::open(&models_dir).map_err(|e| format!("❌ {}", e))?;
+        
+        Ok(registry
+            .entries()
+            .iter()
+            .map(|entry| FrbModelEntry {
+                name: entry.name.clone(),
+                file: entry.file.clone(),
+                size: entry.size.clone(),
+                languages: entry.languages.clone(),
+                has_checksum: entry.sha256.is_some(),
+            })
+            .collect())
+    }
+    
+    /// Verify a named model (e.g. "tiny-ar") and return its path for `FrbTranscriberConfig::model_path`
+    pub fn resolve_model(models_dir: String, model_name: String) -> Result<String, String> {
+        ModelRegistry::open(&models_dir)
+            .and_then(|registry| registry.verify(&model_name))
+            .map(|path| path.to_string_lossy().to_string())
+            .map_err(|e| format!("❌ Model '{}' is not usable: {}", model_name, e))
+    }
+    
+    /// Check a downloaded model file is complete and loadable, without loading it
+    pub fn validate_model_file(model_path: String) -> Result<String, String> {
+        match ModelFile::inspect(&model_path) {
+            Ok(model) => Ok(format!(
+                "✅ {} model ({} tensors, {} bytes) is complete",
+                model.hparams.model_type(),
+                model.n_tensors,
+                model.file_size
+            )),
+            Err(e) => Err(format!("❌ Invalid model file: {}", e)),
+        }
+    }
+    
+    /// Describe the loaded model (size, quantization, languages) to pick defaults per model
+    pub fn get_model_info(instance_id: String) -> Result<FrbModelInfo, String> {
+        let instances = TRANSCRIBER_INSTANCES.lock();
+        
+        if let Some(transcriber) = instances.get(&instance_id) {
+            let info = transcriber.model_info().clone();
+            
+            Ok(FrbModelInfo {
+                model_type: info.model_type,
+                multilingual: info.multilingual,
+                n_vocab: info.n_vocab,
+                n_audio_layer: info.n_audio_layer,
+                n_audio_head: info.n_audio_head,
+                n_text_layer: info.n_text_layer,
+                n_text_head: info.n_text_head,
+                n_mels: info.n_mels,
+                quantization: info.quantization,
+                file_size_bytes: info.file_size,
+                languages: info.languages,
+            })
+        } else {
+            Err(format!("❌ Transcriber instance '{}' not found", instance_id))
+        }
+    }
+    
     /// Get current buffer status
     pub fn get_buffer_status(instance_id: String) -> Result<FrbBufferStatus, String> {
-        let instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+        let instances = TRANSCRIBER_INSTANCES.lock();
         
         if let Some(transcriber) = instances.get(&instance_id) {
             let status = transcriber.get_buffer_status();
@@ -185,7 +364,7 @@ impl FlutterTranscriberApi {
     
     /// Get processing statistics
     pub fn get_processing_stats(instance_id: String) -> Result<FrbProcessingStats, String> {
-        let instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+        let instances = TRANSCRIBER_INSTANCES.lock();
         
         if let Some(transcriber) = instances.get(&instance_id) {
             let stats = transcriber.get_stats();
@@ -202,6 +381,7 @@ impl FlutterTranscriberApi {
                 average_processing_time_ms: stats.average_processing_time_ms,
                 real_time_factor: stats.real_time_factor,
                 buffer_overflows: stats.buffer_overflows,
+                skipped_silent_windows: stats.skipped_silent_windows,
             })
         } else {
             Err(format!("❌ Transcriber instance '{}' not found", instance_id))
@@ -210,9 +390,11 @@ impl FlutterTranscriberApi {
     
     /// Remove transcriber instance and cleanup
     pub fn destroy_transcriber(instance_id: String) -> Result<String, String> {
-        let mut instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+        let mut instances = TRANSCRIBER_INSTANCES.lock();
         
         if let Some(transcriber) = instances.remove(&instance_id) {
+            // Stop a window still being transcribed on another thread
+            transcriber.cancel();
             match transcriber.cleanup() {
                 Ok(()) => Ok(format!("✅ Transcriber '{}' destroyed successfully", instance_id)),
                 Err(e) => Err(format!("⚠️ Transcriber destroyed but cleanup failed: {}", e)),
@@ -224,13 +406,13 @@ impl FlutterTranscriberApi {
     
     /// List all active transcriber instances
     pub fn list_transcribers() -> Vec<String> {
-        let instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+        let instances = TRANSCRIBER_INSTANCES.lock();
         instances.keys().cloned().collect()
     }
     
     /// Health check for transcriber instance
     pub fn health_check(instance_id: String) -> Result<String, String> {
-        let instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+        let instances = TRANSCRIBER_INSTANCES.lock();
         
         if let Some(transcriber) = instances.get(&instance_id) {
             let buffer_status = transcriber.get_buffer_status();
@@ -285,6 +467,8 @@ impl FlutterTranscriberApi {
             window_duration_ms: 3000,  // 3 seconds for better context
             overlap_duration_ms: 1000, // 1 second overlap
             chunk_size_ms: 50,
+            options: TranscribeOptions::default(),
+            skip_silence: true,
         };
         
         Self::create_transcriber(instance_id, config)
@@ -302,6 +486,8 @@ impl FlutterTranscriberApi {
             window_duration_ms: 1500,  // 1.5 seconds for faster response
             overlap_duration_ms: 300,  // 300ms overlap
             chunk_size_ms: 50,
+            options: TranscribeOptions::fast_preview(),
+            skip_silence: true,
         };
         
         Self::create_transcriber(instance_id, config)
@@ -310,7 +496,7 @@ impl FlutterTranscriberApi {
 
 // Global cleanup function
 pub fn cleanup_all_transcribers() -> String {
-    let mut instances = TRANSCRIBER_INSTANCES.lock().unwrap();
+    let mut instances = TRANSCRIBER_INSTANCES.lock();
     let count = instances.len();
     
     for (id, transcriber) in instances.drain() {
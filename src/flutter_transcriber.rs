@@ -1,10 +1,8 @@
 use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use std::fs;
-use std::path::Path;
-use std::process::Command;
-use crate::{init_whisper, free_whisper, WhisperError};
+use std::sync::Arc;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use crate::{CancellationToken, VadConfig, VoiceActivityDetector, ContextOptions, MelFilters, ModelInfo, Segment, Session, TranscribeOptions, Whisper, WhisperError};
 
 /// Production-ready real-time transcriber for Flutter integration
 #[derive(Debug)]
@@ -21,14 +19,23 @@ pub struct FlutterTranscriber {
     
     // Processing state
     last_processed_samples: Arc<Mutex<usize>>,
+    // Samples received since creation, including those dropped from the buffer;
+    // places each window on the stream timeline
+    total_samples: Arc<Mutex<u64>>,
     is_processing: Arc<Mutex<bool>>,
     
     // Model configuration
-    model_path: String,
     language: String,
+    // Read once at load time so it stays available while a window is being transcribed
+    model_info: ModelInfo,
+    mel_filters: MelFilters,
+    // None once cleaned up
+    session: Mutex<Option<Session>>,
+    transcribe_options: Arc<Mutex<TranscribeOptions>>,
+    cancellation: CancellationToken,
     
-    // Temporary files management
-    temp_dir: String,
+    // Silent windows are dropped before reaching whisper
+    speech_gate: Option<Mutex<VoiceActivityDetector>>,
     
     // Performance monitoring
     processing_stats: Arc<Mutex<ProcessingStats>>,
@@ -41,6 +48,8 @@ pub struct ProcessingStats {
     pub average_processing_time_ms: f64,
     pub real_time_factor: f64,
     pub buffer_overflows: u64,
+    /// Windows the voice activity detector found silent and never sent to whisper
+    pub skipped_silent_windows: u64,
     pub last_processing_time: Option<Instant>,
 }
 
@@ -98,6 +107,7 @@ impl Default for ProcessingStats {
             average_processing_time_ms: 0.0,
             real_time_factor: 0.0,
             buffer_overflows: 0,
+            skipped_silent_windows: 0,
             last_processing_time: None,
         }
     }
@@ -126,26 +136,15 @@ impl FlutterTranscriber {
             ));
         }
         
-        // Validate model file exists
-        if !Path::new(&model_path).exists() {
-            return Err(WhisperError::ModelInitError(
-                format!("Model file not found: {}", model_path)
-            ));
-        }
-        
-        // Test model loading
-        let test_instance = init_whisper(&model_path)?;
-        free_whisper(test_instance)?;
+        // Load the model once (checking its layout first, with DTW word timestamps) and keep it for the lifetime of the transcriber
+        let whisper = Whisper::load(&model_path, ContextOptions::with_word_timestamps())?;
+        let model_info = whisper.model().info();
+        let mel_filters = whisper.model().mel_filters().clone();
+        let session = whisper.session();
         
         let max_buffer_duration_ms = window_duration_ms * 5; // 5x window size
         let max_buffer_samples = (sample_rate as u64 * max_buffer_duration_ms as u64 / 1000) as usize;
         
-        // Create temporary directory
-        let temp_dir = format!("/tmp/flutter_transcriber_{}", 
-            SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis());
-        fs::create_dir_all(&temp_dir).map_err(|e| 
-            WhisperError::ProcessingError(format!("Failed to create temp dir: {}", e)))?;
-        
         println!("🎤 Flutter Transcriber initialized:");
         println!("   - Model: {}", model_path);
         println!("   - Language: {}", language);
@@ -153,7 +152,6 @@ impl FlutterTranscriber {
         println!("   - Window: {}ms (overlap: {}ms)", window_duration_ms, overlap_duration_ms);
         println!("   - Chunk size: {}ms", chunk_size_ms);
         println!("   - Max buffer: {}ms", max_buffer_duration_ms);
-        println!("   - Temp dir: {}", temp_dir);
         
         Ok(FlutterTranscriber {
             audio_buffer: Arc::new(Mutex::new(VecDeque::with_capacity(max_buffer_samples))),
@@ -163,23 +161,55 @@ impl FlutterTranscriber {
             chunk_size_ms,
             max_buffer_duration_ms,
             last_processed_samples: Arc::new(Mutex::new(0)),
+            total_samples: Arc::new(Mutex::new(0)),
             is_processing: Arc::new(Mutex::new(false)),
-            model_path,
             language,
-            temp_dir,
+            model_info,
+            mel_filters,
+            session: Mutex::new(Some(session)),
+            transcribe_options: Arc::new(Mutex::new(TranscribeOptions::default())),
+            cancellation: CancellationToken::new(),
+            speech_gate: Some(Mutex::new(VoiceActivityDetector::new(VadConfig {
+                sample_rate,
+                ..VadConfig::default()
+            }))),
             processing_stats: Arc::new(Mutex::new(ProcessingStats::default())),
         })
     }
     
+    /// Use custom decoding options; the transcriber's language applies unless the options set one
+    pub fn with_transcribe_options(self, options: TranscribeOptions) -> Self {
+        *self.transcribe_options.lock() = options;
+        self
+    }
+    
+    /// Replace the voice activity detector gating windows; `None` sends every window to whisper
+    pub fn with_speech_gate(mut self, config: Option<VadConfig>) -> Self {
+        self.speech_gate = config.map(|config| Mutex::new(VoiceActivityDetector::new(config)));
+        self
+    }
+    
+    /// Bias decoding toward the expected text (e.g. the next ayah); `None` clears the prompt
+    pub fn set_initial_prompt(&self, prompt: Option<String>) {
+        let mut options = self.transcribe_options.lock();
+        options.initial_prompt = prompt;
+    }
+    
+    /// Stop the window currently being transcribed (e.g. the user left the screen).
+    /// The interrupted window yields whatever segments were completed before the abort.
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+    
     /// Add audio chunk from Flutter Record (call this every ~50ms)
     pub fn add_audio_chunk(&self, audio_data: &[f32]) -> Result<BufferStatus, WhisperError> {
-        let mut buffer = self.audio_buffer.lock().map_err(|_| 
-            WhisperError::ProcessingError("Buffer lock failed".to_string()))?;
+        let mut buffer = self.audio_buffer.lock();
         
         // Add new samples
         for &sample in audio_data {
             buffer.push_back(sample);
         }
+        *self.total_samples.lock() += audio_data.len() as u64;
         
         // Prevent buffer overflow
         let max_samples = (self.sample_rate as u64 * self.max_buffer_duration_ms as u64 / 1000) as usize;
@@ -190,7 +220,7 @@ impl FlutterTranscriber {
         }
         
         if overflow_count > 0 {
-            let mut stats = self.processing_stats.lock().unwrap();
+            let mut stats = self.processing_stats.lock();
             stats.buffer_overflows += 1;
         }
         
@@ -211,8 +241,7 @@ impl FlutterTranscriber {
     pub fn process_if_ready(&self) -> Result<Option<TranscriptionResult>, WhisperError> {
         // Check if processing is already in progress
         {
-            let is_processing = self.is_processing.lock().map_err(|_| 
-                WhisperError::ProcessingError("Processing lock failed".to_string()))?;
+            let is_processing = self.is_processing.lock();
             if *is_processing {
                 return Ok(None); // Still processing previous window
             }
@@ -220,8 +249,7 @@ impl FlutterTranscriber {
         
         // Check if we have enough data
         let buffer_status = {
-            let buffer = self.audio_buffer.lock().map_err(|_| 
-                WhisperError::ProcessingError("Buffer lock failed".to_string()))?;
+            let buffer = self.audio_buffer.lock();
             
             let current_samples = buffer.len();
             let required_samples = (self.sample_rate as u64 * self.window_duration_ms as u64 / 1000) as usize;
@@ -235,9 +263,9 @@ impl FlutterTranscriber {
         
         // Check if it's time to process (hop duration)
         let should_process = {
-            let last_processed = *self.last_processed_samples.lock().unwrap();
+            let last_processed = *self.last_processed_samples.lock();
             let current_samples = {
-                let buffer = self.audio_buffer.lock().unwrap();
+                let buffer = self.audio_buffer.lock();
                 buffer.len()
             };
             
@@ -252,16 +280,17 @@ impl FlutterTranscriber {
         
         // Mark as processing
         {
-            let mut is_processing = self.is_processing.lock().unwrap();
+            let mut is_processing = self.is_processing.lock();
             *is_processing = true;
         }
         
-        // Process the window
+        // Process the window; a cancel only applies to the window in flight
+        self.cancellation.reset();
         let result = self.process_current_window();
         
         // Mark as not processing
         {
-            let mut is_processing = self.is_processing.lock().unwrap();
+            let mut is_processing = self.is_processing.lock();
             *is_processing = false;
         }
         
@@ -273,31 +302,33 @@ impl FlutterTranscriber {
         let process_start = Instant::now();
         
         // Extract window from buffer
-        let window_samples = {
-            let buffer = self.audio_buffer.lock().unwrap();
+        let (window_samples, window_start_ms) = {
+            let buffer = self.audio_buffer.lock();
             let window_size = (self.sample_rate as u64 * self.window_duration_ms as u64 / 1000) as usize;
             
             if buffer.len() < window_size {
                 return Ok(None);
             }
             
-            // Extract latest window
+            // Extract latest window; the buffer lock keeps it in step with total_samples
             let start_idx = buffer.len() - window_size;
-            buffer.iter().skip(start_idx).cloned().collect::<Vec<f32>>()
+            let window_start = *self.total_samples.lock() - window_size as u64;
+            let window_start_ms = (window_start * 1000 / self.sample_rate as u64) as i64;
+            (buffer.iter().skip(start_idx).cloned().collect::<Vec<f32>>(), window_start_ms)
         };
         
-        // Create temporary WAV file
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
-        let temp_file = format!("{}/window_{}.wav", self.temp_dir, timestamp);
-        
-        // Write audio to temporary file
-        self.write_wav_file(&temp_file, &window_samples)?;
-        
-        // Process with external transcribe_file (most stable approach)
-        let transcription_result = self.transcribe_file(&temp_file)?;
+        // Don't wake whisper for silence: saves battery and avoids hallucinated text
+        if let Some(gate) = &self.speech_gate {
+            if !gate.lock().contains_speech(&window_samples) {
+                self.processing_stats.lock().skipped_silent_windows += 1;
+                let mut last_processed = self.last_processed_samples.lock();
+                *last_processed = self.audio_buffer.lock().len();
+                return Ok(None);
+            }
+        }
         
-        // Clean up temporary file
-        let _ = fs::remove_file(&temp_file);
+        // Transcribe in-process so whisper's segment timestamps are preserved
+        let transcription_result = self.transcribe_window(&window_samples, window_start_ms)?;
         
         // Update processing stats
         let processing_time = process_start.elapsed();
@@ -305,8 +336,8 @@ impl FlutterTranscriber {
         
         // Update last processed samples
         {
-            let mut last_processed = self.last_processed_samples.lock().unwrap();
-            let buffer = self.audio_buffer.lock().unwrap();
+            let mut last_processed = self.last_processed_samples.lock();
+            let buffer = self.audio_buffer.lock();
             *last_processed = buffer.len();
         }
         
@@ -319,102 +350,107 @@ impl FlutterTranscriber {
         }
     }
     
-    /// Write audio samples to WAV file
-    fn write_wav_file(&self, file_path: &str, samples: &[f32]) -> Result<(), WhisperError> {
-        use std::fs::File;
-        use std::io::{Write, BufWriter};
-        
-        let mut file = BufWriter::new(File::create(file_path).map_err(|e| 
-            WhisperError::ProcessingError(format!("Failed to create WAV file: {}", e)))?);
-        
-        // WAV header
-        let num_samples = samples.len() as u32;
-        let byte_rate = self.sample_rate * 2; // 16-bit mono
-        let data_size = num_samples * 2;
-        let file_size = data_size + 36;
-        
-        // RIFF header
-        file.write_all(b"RIFF").unwrap();
-        file.write_all(&file_size.to_le_bytes()).unwrap();
-        file.write_all(b"WAVE").unwrap();
-        
-        // fmt chunk
-        file.write_all(b"fmt ").unwrap();
-        file.write_all(&16u32.to_le_bytes()).unwrap(); // chunk size
-        file.write_all(&1u16.to_le_bytes()).unwrap(); // audio format (PCM)
-        file.write_all(&1u16.to_le_bytes()).unwrap(); // num channels
-        file.write_all(&self.sample_rate.to_le_bytes()).unwrap(); // sample rate
-        file.write_all(&byte_rate.to_le_bytes()).unwrap(); // byte rate
-        file.write_all(&2u16.to_le_bytes()).unwrap(); // block align
-        file.write_all(&16u16.to_le_bytes()).unwrap(); // bits per sample
-        
-        // data chunk
-        file.write_all(b"data").unwrap();
-        file.write_all(&data_size.to_le_bytes()).unwrap();
-        
-        // Convert f32 samples to i16 and write
-        for sample in samples {
-            let i16_sample = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
-            file.write_all(&i16_sample.to_le_bytes()).unwrap();
+    /// Transcribe a window of samples with the transcriber's own whisper instance.
+    /// Times in the result are relative to the start of the stream.
+    fn transcribe_window(&self, samples: &[f32], window_start_ms: i64) -> Result<Option<TranscriptionResult>, WhisperError> {
+        let mut options = self.transcribe_options.lock().clone();
+        if options.language.is_none() {
+            options.language = Some(self.language.clone());
         }
         
-        Ok(())
+        options.cancellation = Some(self.cancellation.clone());
+        
+        let mut session = self.session.lock();
+        let session = session
+            .as_mut()
+            .ok_or_else(|| WhisperError::ModelInitError("Transcriber has been cleaned up".to_string()))?;
+        
+        // With VAD configured, windows without speech never reach whisper
+        let result = if options.vad.is_some() {
+            session.transcribe_with_speech_regions(samples, &options)
+                .map(|transcript| transcript.segments)
+        } else {
+            session.transcribe(samples, &options)
+        };
+        
+        match result {
+            Ok(segments) => Ok(Self::segments_to_result(&segments, window_start_ms)),
+            Err(e) => match e.partial_segments() {
+                Some(completed) => Ok(Self::segments_to_result(completed, window_start_ms)),
+                None => Err(e),
+            },
+        }
     }
     
-    /// Transcribe audio file using external process (most stable)
-    fn transcribe_file(&self, file_path: &str) -> Result<Option<TranscriptionResult>, WhisperError> {
-        let output = Command::new("./target/debug/examples/transcribe_file")
-            .args(&[&self.model_path, file_path, &self.language])
-            .output()
-            .map_err(|e| WhisperError::ProcessingError(format!("Transcription failed: {}", e)))?;
-        
-        if !output.status.success() {
-            return Err(WhisperError::ProcessingError(
-                format!("Transcription process failed: {}", String::from_utf8_lossy(&output.stderr))
-            ));
-        }
+    /// Build a transcription result from the segments of a window starting at
+    /// `window_start_ms` in the stream
+    fn segments_to_result(segments: &[Segment], window_start_ms: i64) -> Option<TranscriptionResult> {
+        let spoken: Vec<Segment> = segments
+            .iter()
+            .filter(|segment| !segment.text.trim().is_empty())
+            .map(|segment| segment.clone().offset(window_start_ms))
+            .collect();
+        
+        let (first, last) = match (spoken.first(), spoken.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return None,
+        };
+        
+        let text = spoken
+            .iter()
+            .map(|segment| segment.text.trim())
+            .collect::<Vec<_>>()
+            .join(" ");
+        
+        let words: Vec<WordResult> = spoken
+            .iter()
+            .flat_map(|segment| segment.words())
+            .map(|word| WordResult {
+                word: word.text,
+                start_time_ms: word.t0_ms.max(0) as u64,
+                end_time_ms: word.t1_ms.max(0) as u64,
+                confidence: word.probability as f64,
+            })
+            .collect();
+        
+        // Mean probability over every text token in the window
+        let probabilities: Vec<f32> = spoken
+            .iter()
+            .flat_map(|segment| segment.text_tokens().map(|token| token.p))
+            .collect();
+        let confidence = if probabilities.is_empty() {
+            0.0
+        } else {
+            probabilities.iter().sum::<f32>() as f64 / probabilities.len() as f64
+        };
         
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        self.parse_transcription_output(&output_str)
+        Some(TranscriptionResult {
+            text,
+            start_time_ms: first.t0_ms.max(0) as u64,
+            end_time_ms: last.t1_ms.max(0) as u64,
+            confidence,
+            words,
+            processing_time_ms: 0, // Will be set by caller
+            is_real_time: true, // Will be set by caller
+        })
     }
     
-    /// Parse transcription output
-    fn parse_transcription_output(&self, output: &str) -> Result<Option<TranscriptionResult>, WhisperError> {
-        // Find transcription markers
-        if let Some(start_marker) = output.find("-------------------------------------------") {
-            if let Some(content_start) = output[start_marker..].find('\n') {
-                let content_section = &output[start_marker + content_start + 1..];
-                if let Some(end_marker) = content_section.find("-------------------------------------------") {
-                    let transcription = content_section[..end_marker].trim();
-                    
-                    if !transcription.is_empty() {
-                        // Extract words (simple implementation, can be enhanced)
-                        let words: Vec<WordResult> = transcription
-                            .split_whitespace()
-                            .enumerate()
-                            .map(|(i, word)| WordResult {
-                                word: word.to_string(),
-                                start_time_ms: (i as u64 * 500), // Rough estimate
-                                end_time_ms: ((i + 1) as u64 * 500),
-                                confidence: 0.95, // Default confidence
-                            })
-                            .collect();
-                        
-                        return Ok(Some(TranscriptionResult {
-                            text: transcription.to_string(),
-                            start_time_ms: 0,
-                            end_time_ms: self.window_duration_ms as u64,
-                            confidence: 0.95,
-                            words,
-                            processing_time_ms: 0, // Will be set by caller
-                            is_real_time: true, // Will be set by caller
-                        }));
-                    }
-                }
-            }
+    /// Detect the spoken language of `audio_data`, most likely first
+    pub fn detect_language(&self, audio_data: &[f32]) -> Result<Vec<(String, f32)>, WhisperError> {
+        match self.session.lock().as_ref() {
+            Some(session) => session.detect_language(audio_data, TranscribeOptions::default().n_threads),
+            None => Err(WhisperError::ModelInitError("Transcriber has been cleaned up".to_string())),
         }
-        
-        Ok(None) // No transcription found
+    }
+    
+    /// Architecture, quantization and languages of the loaded model
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.model_info
+    }
+    
+    /// Mel filter bank of the loaded model, for computing spectrograms as whisper.cpp does
+    pub fn mel_filters(&self) -> &MelFilters {
+        &self.mel_filters
     }
     
     /// Validate transcribed text against expected content
@@ -457,12 +493,12 @@ impl FlutterTranscriber {
     
     /// Get current processing statistics
     pub fn get_stats(&self) -> ProcessingStats {
-        self.processing_stats.lock().unwrap().clone()
+        self.processing_stats.lock().clone()
     }
     
     /// Get current buffer status
     pub fn get_buffer_status(&self) -> BufferStatus {
-        let buffer = self.audio_buffer.lock().unwrap();
+        let buffer = self.audio_buffer.lock();
         let current_duration_ms = (buffer.len() as u64 * 1000) / self.sample_rate as u64;
         let max_samples = (self.sample_rate as u64 * self.max_buffer_duration_ms as u64 / 1000) as usize;
         let buffer_usage = buffer.len() as f64 / max_samples as f64;
@@ -478,11 +514,8 @@ impl FlutterTranscriber {
     
     /// Clean up resources
     pub fn cleanup(&self) -> Result<(), WhisperError> {
-        // Remove temporary directory
-        if Path::new(&self.temp_dir).exists() {
-            fs::remove_dir_all(&self.temp_dir).map_err(|e| 
-                WhisperError::ProcessingError(format!("Cleanup failed: {}", e)))?;
-        }
+        // Release the session (a no-op if it was already released)
+        self.session.lock().take();
         
         println!("🧹 Flutter Transcriber cleaned up");
         Ok(())
@@ -545,7 +578,7 @@ impl FlutterTranscriber {
     }
     
     fn update_stats(&self, processing_time: Duration, success: bool) {
-        let mut stats = self.processing_stats.lock().unwrap();
+        let mut stats = self.processing_stats.lock();
         stats.total_processed_windows += 1;
         
         if success {
@@ -566,3 +599,41 @@ impl Drop for FlutterTranscriber {
         let _ = self.cleanup();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenData;
+
+    fn segment(text: &str, t0_ms: i64, t1_ms: i64) -> Segment {
+        Segment {
+            text: text.to_string(),
+            t0_ms,
+            t1_ms,
+            no_speech_prob: 0.0,
+            tokens: vec![TokenData {
+                id: 0,
+                text: text.to_string(),
+                p: 0.9,
+                plog: 0.9f32.ln(),
+                t0_ms,
+                t1_ms,
+                t_dtw_ms: None,
+                special: false,
+                bytes: text.as_bytes().to_vec(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_window_results_are_on_the_stream_timeline() {
+        // Two overlapping 3 s windows 2 s apart; whisper reports times within each window
+        let first = FlutterTranscriber::segments_to_result(&[segment(" بسم", 500, 1500)], 0).unwrap();
+        let second = FlutterTranscriber::segments_to_result(&[segment(" الله", 1200, 2000)], 2000).unwrap();
+
+        assert_eq!((first.start_time_ms, first.end_time_ms), (500, 1500));
+        assert_eq!((second.start_time_ms, second.end_time_ms), (3200, 4000));
+        assert!(second.start_time_ms > first.end_time_ms);
+        assert_eq!((second.words[0].start_time_ms, second.words[0].end_time_ms), (3200, 4000));
+    }
+}
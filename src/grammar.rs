@@ -0,0 +1,479 @@
+//! GBNF grammars for constrained decoding
+//!
+//! A port of whisper.cpp's `grammar-parser.cpp`: GBNF text is parsed into the
+//! `whisper_grammar_element` rules that `whisper_full_params.grammar_rules` expects.
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use serde::{Deserialize, Serialize};
+use crate::WhisperError;
+
+/// Element kinds (values mirror `enum whisper_gretype`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum GrammarElementType {
+    /// End of rule definition
+    End = 0,
+    /// Start of alternate definition for rule
+    Alt = 1,
+    /// Non-terminal element: reference to rule
+    RuleRef = 2,
+    /// Terminal element: character (code point)
+    Char = 3,
+    /// Inverse char(s) (`[^a]`, `[^a-b]`, `[^abc]`)
+    CharNot = 4,
+    /// Modifies a preceding `Char` or `CharAlt` to be an inclusive range (`[a-z]`)
+    CharRngUpper = 5,
+    /// Modifies a preceding `Char` or `CharRngUpper` to add an alternate char to match (`[ab]`, `[a-zA]`)
+    CharAlt = 6,
+}
+
+/// `whisper_grammar_element`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct GrammarElement {
+    pub kind: GrammarElementType,
+    /// Unicode code point or rule id
+    pub value: u32,
+}
+
+impl GrammarElement {
+    fn new(kind: GrammarElementType, value: u32) -> Self {
+        Self { kind, value }
+    }
+}
+
+/// A parsed GBNF grammar. Serializes as its GBNF source text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Grammar {
+    source: String,
+    rules: Vec<Vec<GrammarElement>>,
+    symbol_ids: HashMap<String, u32>,
+}
+
+impl Grammar {
+    /// Parse GBNF text, e.g. `root ::= " yes" | " no"`
+    pub fn parse(source: &str) -> Result<Self, WhisperError> {
+        let mut parser = Parser {
+            src: source,
+            symbol_ids: HashMap::new(),
+            rules: Vec::new(),
+        };
+        parser.parse()?;
+
+        Ok(Self {
+            source: source.to_string(),
+            rules: parser.rules,
+            symbol_ids: parser.symbol_ids,
+        })
+    }
+
+    /// Grammar whose `root` rule matches exactly one of `phrases`.
+    /// whisper emits a leading space before the first word, so one is allowed here.
+    pub fn from_phrases<S: AsRef<str>>(phrases: &[S]) -> Result<Self, WhisperError> {
+        if phrases.is_empty() {
+            return Err(WhisperError::InvalidParameter("At least one phrase is required".to_string()));
+        }
+
+        let alternates: Vec<String> = phrases
+            .iter()
+            .map(|phrase| format!("\"{}\"", escape_literal(phrase.as_ref())))
+            .collect();
+
+        Self::parse(&format!("root ::= \" \"? ( {} )\n", alternates.join(" | ")))
+    }
+
+    /// GBNF source this grammar was parsed from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn rules(&self) -> &[Vec<GrammarElement>] {
+        &self.rules
+    }
+
+    /// Index of the rule called `name`, used as `i_start_rule`
+    pub fn rule_index(&self, name: &str) -> Option<usize> {
+        self.symbol_ids.get(name).map(|&id| id as usize)
+    }
+
+    /// One pointer per rule, as `whisper_full_params.grammar_rules` expects
+    pub(crate) fn rule_pointers(&self) -> Vec<*const c_void> {
+        self.rules
+            .iter()
+            .map(|rule| rule.as_ptr() as *const c_void)
+            .collect()
+    }
+}
+
+impl TryFrom<String> for Grammar {
+    type Error = WhisperError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Grammar::parse(&source)
+    }
+}
+
+impl From<Grammar> for String {
+    fn from(grammar: Grammar) -> Self {
+        grammar.source
+    }
+}
+
+fn escape_literal(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'-'
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    symbol_ids: HashMap<String, u32>,
+    rules: Vec<Vec<GrammarElement>>,
+}
+
+impl Parser<'_> {
+    fn parse(&mut self) -> Result<(), WhisperError> {
+        let mut pos = self.parse_space(0, true);
+        while pos < self.src.len() {
+            pos = self.parse_rule(pos)?;
+        }
+
+        // Every referenced rule must have been defined
+        for rule in &self.rules {
+            for element in rule {
+                if element.kind == GrammarElementType::RuleRef {
+                    let defined = self.rules
+                        .get(element.value as usize)
+                        .is_some_and(|rule| !rule.is_empty());
+                    if !defined {
+                        let name = self.symbol_ids
+                            .iter()
+                            .find(|(_, &id)| id == element.value)
+                            .map_or("?", |(name, _)| name.as_str());
+                        return Err(grammar_error(format!("Undefined rule identifier '{}'", name)));
+                    }
+                }
+            }
+        }
+
+        if self.rules.is_empty() {
+            return Err(grammar_error("Grammar has no rules".to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn byte(&self, pos: usize) -> Option<u8> {
+        self.src.as_bytes().get(pos).copied()
+    }
+
+    fn error_at(&self, message: &str, pos: usize) -> WhisperError {
+        let rest: String = self.src.get(pos..).unwrap_or("").chars().take(20).collect();
+        grammar_error(format!("{} at '{}'", message, rest))
+    }
+
+    fn symbol_id(&mut self, name: &str) -> u32 {
+        let next_id = self.symbol_ids.len() as u32;
+        *self.symbol_ids.entry(name.to_string()).or_insert(next_id)
+    }
+
+    fn generate_symbol_id(&mut self, base_name: &str) -> u32 {
+        let next_id = self.symbol_ids.len() as u32;
+        self.symbol_ids.insert(format!("{}_{}", base_name, next_id), next_id);
+        next_id
+    }
+
+    fn add_rule(&mut self, rule_id: u32, rule: Vec<GrammarElement>) {
+        let index = rule_id as usize;
+        if self.rules.len() <= index {
+            self.rules.resize(index + 1, Vec::new());
+        }
+        self.rules[index] = rule;
+    }
+
+    fn parse_space(&self, mut pos: usize, newline_ok: bool) -> usize {
+        while let Some(c) = self.byte(pos) {
+            match c {
+                b' ' | b'\t' => pos += 1,
+                b'\r' | b'\n' if newline_ok => pos += 1,
+                b'#' => {
+                    while !matches!(self.byte(pos), None | Some(b'\r') | Some(b'\n')) {
+                        pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        pos
+    }
+
+    fn parse_name(&self, pos: usize) -> Result<usize, WhisperError> {
+        let mut end = pos;
+        while self.byte(end).is_some_and(is_word_char) {
+            end += 1;
+        }
+        if end == pos {
+            return Err(self.error_at("expecting name", pos));
+        }
+        Ok(end)
+    }
+
+    fn parse_hex(&self, pos: usize, size: usize) -> Result<(u32, usize), WhisperError> {
+        let digits = self.src
+            .get(pos..pos + size)
+            .filter(|digits| digits.bytes().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error_at(&format!("expecting {} hex chars", size), pos))?;
+        let value = u32::from_str_radix(digits, 16)
+            .map_err(|_| self.error_at("invalid hex value", pos))?;
+        Ok((value, pos + size))
+    }
+
+    fn parse_char(&self, pos: usize) -> Result<(u32, usize), WhisperError> {
+        if self.byte(pos) == Some(b'\\') {
+            return match self.byte(pos + 1) {
+                Some(b'x') => self.parse_hex(pos + 2, 2),
+                Some(b'u') => self.parse_hex(pos + 2, 4),
+                Some(b'U') => self.parse_hex(pos + 2, 8),
+                Some(b't') => Ok(('\t' as u32, pos + 2)),
+                Some(b'r') => Ok(('\r' as u32, pos + 2)),
+                Some(b'n') => Ok(('\n' as u32, pos + 2)),
+                Some(c @ (b'\\' | b'"' | b'[' | b']')) => Ok((c as u32, pos + 2)),
+                _ => Err(self.error_at("unknown escape", pos)),
+            };
+        }
+
+        match self.src.get(pos..).and_then(|rest| rest.chars().next()) {
+            Some(c) => Ok((c as u32, pos + c.len_utf8())),
+            None => Err(self.error_at("unexpected end of input", pos)),
+        }
+    }
+
+    fn parse_sequence(
+        &mut self,
+        mut pos: usize,
+        rule_name: &str,
+        out: &mut Vec<GrammarElement>,
+        is_nested: bool,
+    ) -> Result<usize, WhisperError> {
+        let mut last_sym_start = out.len();
+
+        while let Some(c) = self.byte(pos) {
+            if c == b'"' {
+                // literal string
+                pos += 1;
+                last_sym_start = out.len();
+                while self.byte(pos) != Some(b'"') {
+                    if self.byte(pos).is_none() {
+                        return Err(self.error_at("unexpected end of input", pos));
+                    }
+                    let (value, next) = self.parse_char(pos)?;
+                    out.push(GrammarElement::new(GrammarElementType::Char, value));
+                    pos = next;
+                }
+                pos = self.parse_space(pos + 1, is_nested);
+            } else if c == b'[' {
+                // char range(s)
+                pos += 1;
+                let mut start_type = GrammarElementType::Char;
+                if self.byte(pos) == Some(b'^') {
+                    pos += 1;
+                    start_type = GrammarElementType::CharNot;
+                }
+                last_sym_start = out.len();
+                while self.byte(pos) != Some(b']') {
+                    if self.byte(pos).is_none() {
+                        return Err(self.error_at("unexpected end of input", pos));
+                    }
+                    let (value, next) = self.parse_char(pos)?;
+                    let kind = if last_sym_start < out.len() {
+                        GrammarElementType::CharAlt
+                    } else {
+                        start_type
+                    };
+                    out.push(GrammarElement::new(kind, value));
+                    pos = next;
+
+                    if self.byte(pos) == Some(b'-') && !matches!(self.byte(pos + 1), None | Some(b']')) {
+                        let (upper, next) = self.parse_char(pos + 1)?;
+                        out.push(GrammarElement::new(GrammarElementType::CharRngUpper, upper));
+                        pos = next;
+                    }
+                }
+                pos = self.parse_space(pos + 1, is_nested);
+            } else if is_word_char(c) {
+                // rule reference
+                let name_end = self.parse_name(pos)?;
+                let ref_rule_id = self.symbol_id(&self.src[pos..name_end]);
+                pos = self.parse_space(name_end, is_nested);
+                last_sym_start = out.len();
+                out.push(GrammarElement::new(GrammarElementType::RuleRef, ref_rule_id));
+            } else if c == b'(' {
+                // grouping: parse nested alternates into a synthesized rule
+                let sub_rule_id = self.generate_symbol_id(rule_name);
+                pos = self.parse_space(pos + 1, true);
+                pos = self.parse_alternates(pos, rule_name, sub_rule_id, true)?;
+                last_sym_start = out.len();
+                out.push(GrammarElement::new(GrammarElementType::RuleRef, sub_rule_id));
+                if self.byte(pos) != Some(b')') {
+                    return Err(self.error_at("expecting ')'", pos));
+                }
+                pos = self.parse_space(pos + 1, is_nested);
+            } else if matches!(c, b'*' | b'+' | b'?') {
+                if last_sym_start == out.len() {
+                    return Err(self.error_at("expecting preceding item to */+/?", pos));
+                }
+
+                // apply transformation to previous symbol (last_sym_start to end) according to rewrite rules:
+                // S* --> S' ::= S S' |
+                // S+ --> S' ::= S S' | S
+                // S? --> S' ::= S |
+                let sub_rule_id = self.generate_symbol_id(rule_name);
+                let symbol: Vec<GrammarElement> = out[last_sym_start..].to_vec();
+                let mut sub_rule = symbol.clone();
+                if c == b'*' || c == b'+' {
+                    sub_rule.push(GrammarElement::new(GrammarElementType::RuleRef, sub_rule_id));
+                }
+                sub_rule.push(GrammarElement::new(GrammarElementType::Alt, 0));
+                if c == b'+' {
+                    sub_rule.extend_from_slice(&symbol);
+                }
+                sub_rule.push(GrammarElement::new(GrammarElementType::End, 0));
+                self.add_rule(sub_rule_id, sub_rule);
+
+                // in original rule, replace previous symbol with reference to generated rule
+                out.truncate(last_sym_start);
+                out.push(GrammarElement::new(GrammarElementType::RuleRef, sub_rule_id));
+                pos = self.parse_space(pos + 1, is_nested);
+            } else {
+                break;
+            }
+        }
+
+        Ok(pos)
+    }
+
+    fn parse_alternates(
+        &mut self,
+        pos: usize,
+        rule_name: &str,
+        rule_id: u32,
+        is_nested: bool,
+    ) -> Result<usize, WhisperError> {
+        let mut rule = Vec::new();
+        let mut pos = self.parse_sequence(pos, rule_name, &mut rule, is_nested)?;
+
+        while self.byte(pos) == Some(b'|') {
+            rule.push(GrammarElement::new(GrammarElementType::Alt, 0));
+            pos = self.parse_space(pos + 1, true);
+            pos = self.parse_sequence(pos, rule_name, &mut rule, is_nested)?;
+        }
+
+        rule.push(GrammarElement::new(GrammarElementType::End, 0));
+        self.add_rule(rule_id, rule);
+        Ok(pos)
+    }
+
+    fn parse_rule(&mut self, pos: usize) -> Result<usize, WhisperError> {
+        let name_end = self.parse_name(pos)?;
+        let name = self.src[pos..name_end].to_string();
+        let rule_id = self.symbol_id(&name);
+
+        let pos = self.parse_space(name_end, false);
+        if !self.src[pos..].starts_with("::=") {
+            return Err(self.error_at("expecting ::=", pos));
+        }
+        let pos = self.parse_space(pos + 3, true);
+        let mut pos = self.parse_alternates(pos, &name, rule_id, false)?;
+
+        match self.byte(pos) {
+            Some(b'\r') => pos += if self.byte(pos + 1) == Some(b'\n') { 2 } else { 1 },
+            Some(b'\n') => pos += 1,
+            Some(_) => return Err(self.error_at("expecting newline or end", pos)),
+            None => {}
+        }
+
+        Ok(self.parse_space(pos, true))
+    }
+}
+
+fn grammar_error(message: String) -> WhisperError {
+    WhisperError::InvalidParameter(format!("Invalid grammar: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use GrammarElementType::*;
+
+    fn el(kind: GrammarElementType, value: u32) -> GrammarElement {
+        GrammarElement::new(kind, value)
+    }
+
+    #[test]
+    fn test_parse_alternates_and_char_class() {
+        let grammar = Grammar::parse("root ::= \"ab\" | [a-c^]\n").unwrap();
+
+        assert_eq!(grammar.rule_index("root"), Some(0));
+        assert_eq!(
+            grammar.rules()[0],
+            vec![
+                el(Char, 'a' as u32),
+                el(Char, 'b' as u32),
+                el(Alt, 0),
+                el(Char, 'a' as u32),
+                el(CharRngUpper, 'c' as u32),
+                el(CharAlt, '^' as u32),
+                el(End, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repetition_is_rewritten_into_rules() {
+        let grammar = Grammar::parse("root ::= word+\nword ::= [a-z]\n").unwrap();
+
+        // root ::= root_2 ; root_2 ::= word root_2 | word
+        let word = grammar.rule_index("word").unwrap() as u32;
+        let generated = grammar.rule_index("root_2").unwrap() as u32;
+        assert_eq!(grammar.rules()[0], vec![el(RuleRef, generated), el(End, 0)]);
+        assert_eq!(
+            grammar.rules()[generated as usize],
+            vec![el(RuleRef, word), el(RuleRef, generated), el(Alt, 0), el(RuleRef, word), el(End, 0)]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Grammar::parse("root ::= missing\n").is_err());
+        assert!(Grammar::parse("root = \"a\"\n").is_err());
+        assert!(Grammar::parse("root ::= \"unterminated\n").is_err());
+        assert!(Grammar::parse("root ::= *\n").is_err());
+        assert!(Grammar::parse("").is_err());
+    }
+
+    #[test]
+    fn test_from_phrases_escapes_and_roundtrips() {
+        let grammar = Grammar::from_phrases(&["بسم الله", "say \"stop\""]).unwrap();
+        assert_eq!(grammar.rule_index("root"), Some(0));
+
+        let json = serde_json::to_string(&grammar).unwrap();
+        let parsed: Grammar = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, grammar);
+        assert!(parsed.source().contains("say \\\"stop\\\""));
+    }
+}
@@ -19,16 +19,98 @@ pub mod flutter_api;
 // Quran integration for dual-project setup
 pub mod quran_integration;
 
+// Timestamped segment results
+pub mod segment;
+
+pub use segment::{segments_to_text, Segment, SpeechRegion, TokenData, Transcript, Word};
+
+// Model loading and transcription options
+pub mod options;
+
+pub use options::{AlignmentHeadsPreset, ContextOptions, SamplingStrategy, TranscribeOptions, VadOptions};
+
+// GBNF grammars for constrained decoding
+pub mod grammar;
+
+pub use grammar::{Grammar, GrammarElement, GrammarElementType};
+
+// Cancellation of in-flight transcriptions
+pub mod cancel;
+
+pub use cancel::CancellationToken;
+
+// Model-free voice activity detection
+pub mod vad;
+
+pub use vad::{VadConfig, VoiceActivityDetector};
+
+// Shared models and pooled decoding states
+pub mod model;
+
+pub use model::{Model, ModelInfo, DEFAULT_MAX_IDLE_STATES};
+
+// Offline model file validation
+pub mod model_file;
+
+pub use model_file::{Hparams, ModelFile};
+
+// Named models in a local directory, verified against a manifest
+pub mod model_registry;
+
+pub use model_registry::{ModelEntry, ModelRegistry};
+
+// Log-mel spectrograms
+pub mod mel;
+
+pub use mel::{log_mel_spectrogram, log_mel_spectrogram_with, Mel, MelFilters};
+
+// Vocabulary of a loaded model
+pub mod tokenizer;
+
+pub use tokenizer::{SpecialTokens, TokenId, Tokenizer};
+
+// Logits filters run at every decoding step
+pub mod logits;
+
+pub use logits::{LogitsFilter, SharedLogitsFilter, TokenBias};
+
+// Offline model quantization
+pub mod quantize;
+
+pub use quantize::{quantize_model, QuantizationType, QuantizeReport};
+
+// Safe Rust API: loaded models and decoding sessions
+pub mod whisper;
+
+pub use whisper::{Session, Whisper};
+
+// Progress and new-segment callback trampolines
+mod callbacks;
+
+// Id-to-instance map used by the C API
+mod registry;
+
+// Model loading from readers (file descriptors, streams)
+mod loader;
+
+// In-place FFT for the mel front-end and the VAD
+mod fft;
+
+// Error codes and last-error reporting for the C API
+pub mod error_code;
+
+pub use error_code::ErrorCode;
+
 use std::ffi::{c_char, c_float, c_int, c_void, CStr, CString};
-use std::sync::{Arc, Mutex};
-use std::ptr::null_mut;
-use std::path::Path;
+use std::sync::Arc;
 use std::slice;
 use std::collections::HashMap;
 use anyhow::Result;
 use once_cell::sync::Lazy;
-use log::error;
+use parking_lot::Mutex;
 use thiserror::Error;
+use registry::Registry;
+use error_code::{catch_panic, guard, last_error_code, last_error_message};
 
 #[derive(Error, Debug)]
 pub enum WhisperError {
@@ -49,20 +131,71 @@ pub enum WhisperError {
 
     #[error("Internal error: {0}")]
     InternalError(String),
+
+    /// Stopped through a `CancellationToken`; holds the segments completed before the abort
+    #[error("Transcription cancelled after {} segment(s)", .0.len())]
+    Cancelled(Vec<Segment>),
+
+    /// Stopped by `timeout_ms`; holds the segments completed before the abort
+    #[error("Transcription deadline exceeded after {} segment(s)", .0.len())]
+    DeadlineExceeded(Vec<Segment>),
+
+    /// A C result buffer was too small; holds the size needed, including the NUL
+    #[error("Result buffer too small: {0} bytes needed")]
+    BufferTooSmall(usize),
+
+    /// The model file ends before all of its data, e.g. an interrupted download
+    #[error("Model file is truncated: {0}")]
+    TruncatedModel(String),
+
+    /// Not a ggml whisper model, e.g. a GGUF file or an HTML error page
+    #[error("Unsupported model format: {0}")]
+    UnsupportedModelFormat(String),
+
+    /// A model file doesn't match the SHA-256 in its registry manifest
+    #[error("Model checksum mismatch: {0}")]
+    ChecksumMismatch(String),
+}
+
+impl WhisperError {
+    /// Segments completed before a cancellation or deadline stopped the call
+    pub fn partial_segments(&self) -> Option<&[Segment]> {
+        match self {
+            WhisperError::Cancelled(segments) | WhisperError::DeadlineExceeded(segments) => Some(segments),
+            _ => None,
+        }
+    }
+
+    /// Shift an abort's partial segments by the start of the window they came from
+    fn offset_partial(self, offset_ms: i64) -> Self {
+        self.map_partial(|segments| segments.into_iter().map(|s| s.offset(offset_ms)).collect())
+    }
+
+    /// Put segments from earlier windows in front of an abort's partial segments
+    fn with_earlier_segments(self, earlier: Vec<Segment>) -> Self {
+        self.map_partial(|segments| earlier.into_iter().chain(segments).collect())
+    }
+
+    fn map_partial(self, f: impl FnOnce(Vec<Segment>) -> Vec<Segment>) -> Self {
+        match self {
+            WhisperError::Cancelled(segments) => WhisperError::Cancelled(f(segments)),
+            WhisperError::DeadlineExceeded(segments) => WhisperError::DeadlineExceeded(f(segments)),
+            other => other,
+        }
+    }
 }
 
 // Global static instance manager
-static INSTANCES: Lazy<Mutex<HashMap<i32, Arc<Mutex<WhisperContext>>>>> = 
-    Lazy::new(|| Mutex::new(HashMap::new()));
+static INSTANCES: Lazy<Registry<Session>> = Lazy::new(Registry::new);
 
-static NEXT_INSTANCE_ID: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(0));
+// Per-instance cancellation and model, kept outside INSTANCES so cancelling or
+// inspecting an instance never waits on a running call
+static INSTANCE_HANDLES: Lazy<Mutex<HashMap<i32, InstanceHandle>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
-#[repr(C)]
-pub struct WhisperContext {
-    ctx: *mut c_void,
-    state: *mut c_void,
-    is_loaded: bool,
-    model_path: String,
+struct InstanceHandle {
+    cancellation: CancellationToken,
+    model: Arc<Model>,
 }
 
 // FFI bindings to whisper.cpp
@@ -71,15 +204,39 @@ mod ffi {
 
     unsafe extern "C" {
         // Context management
-        pub fn whisper_init_from_file_with_params(path: *const c_char, params: WhisperContextParams) -> *mut c_void;
+        pub fn whisper_init_from_file_with_params_no_state(path: *const c_char, params: WhisperContextParams) -> *mut c_void;
+        pub fn whisper_init_from_buffer_with_params_no_state(buffer: *mut c_void, buffer_size: usize, params: WhisperContextParams) -> *mut c_void;
+        pub fn whisper_init_with_params_no_state(loader: *mut WhisperModelLoader, params: WhisperContextParams) -> *mut c_void;
         pub fn whisper_free(ctx: *mut c_void);
         pub fn whisper_init_state(ctx: *mut c_void) -> *mut c_void;
         pub fn whisper_free_state(state: *mut c_void);
 
         // Model info
-        pub fn whisper_lang_id(ctx: *mut c_void, lang: *const c_char) -> c_int;
-        pub fn whisper_version() -> *const c_char;
+        pub fn whisper_model_n_vocab(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_audio_ctx(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_audio_state(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_audio_head(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_audio_layer(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_text_ctx(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_text_state(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_text_head(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_text_layer(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_n_mels(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_ftype(ctx: *mut c_void) -> c_int;
+        pub fn whisper_model_type_readable(ctx: *mut c_void) -> *const c_char;
+        pub fn whisper_is_multilingual(ctx: *mut c_void) -> c_int;
+        pub fn whisper_lang_id(lang: *const c_char) -> c_int;
         pub fn whisper_lang_str(lang_id: c_int) -> *const c_char;
+        pub fn whisper_lang_max_id() -> c_int;
+
+        // Language detection (requires the mel spectrogram to be computed first)
+        pub fn whisper_lang_auto_detect_with_state(
+            ctx: *mut c_void,
+            state: *mut c_void,
+            offset_ms: c_int,
+            n_threads: c_int,
+            lang_probs: *mut c_float
+        ) -> c_int;
 
         // Audio processing
         pub fn whisper_pcm_to_mel_with_state(
@@ -89,6 +246,14 @@ mod ffi {
             n_samples: c_int,
             n_threads: c_int
         ) -> c_int;
+        // `data` is n_mel rows of n_len frames
+        pub fn whisper_set_mel_with_state(
+            ctx: *mut c_void,
+            state: *mut c_void,
+            data: *const c_float,
+            n_len: c_int,
+            n_mel: c_int
+        ) -> c_int;
 
         pub fn whisper_full_with_state(
             ctx: *mut c_void,
@@ -103,13 +268,62 @@ mod ffi {
         pub fn whisper_full_n_segments_from_state(state: *mut c_void) -> c_int;
         pub fn whisper_full_get_segment_text(ctx: *mut c_void, segment_id: c_int) -> *const c_char;
         pub fn whisper_full_get_segment_text_from_state(state: *mut c_void, segment_id: c_int) -> *const c_char;
+        pub fn whisper_full_get_segment_t0_from_state(state: *mut c_void, segment_id: c_int) -> i64;
+        pub fn whisper_full_get_segment_t1_from_state(state: *mut c_void, segment_id: c_int) -> i64;
+        pub fn whisper_full_get_segment_no_speech_prob_from_state(state: *mut c_void, segment_id: c_int) -> c_float;
+        pub fn whisper_full_n_tokens_from_state(state: *mut c_void, segment_id: c_int) -> c_int;
+        pub fn whisper_full_get_token_text_from_state(ctx: *mut c_void, state: *mut c_void, segment_id: c_int, token_id: c_int) -> *const c_char;
+        pub fn whisper_full_get_token_data_from_state(state: *mut c_void, segment_id: c_int, token_id: c_int) -> WhisperTokenData;
+        pub fn whisper_token_eot(ctx: *mut c_void) -> c_int;
         pub fn whisper_full_n_tokens(ctx: *mut c_void, segment_id: c_int) -> c_int;
         pub fn whisper_full_get_token_text(ctx: *mut c_void, token_id: c_int) -> *const c_char;
         pub fn whisper_full_get_token_data(ctx: *mut c_void, segment_id: c_int, token_id: c_int) -> WhisperTokenData;
 
+        // Vocabulary
+        pub fn whisper_n_vocab(ctx: *mut c_void) -> c_int;
+        pub fn whisper_tokenize(ctx: *mut c_void, text: *const c_char, tokens: *mut c_int, n_max_tokens: c_int) -> c_int;
+        pub fn whisper_token_count(ctx: *mut c_void, text: *const c_char) -> c_int;
+        pub fn whisper_token_to_str(ctx: *mut c_void, token: c_int) -> *const c_char;
+        pub fn whisper_token_sot(ctx: *mut c_void) -> c_int;
+        pub fn whisper_token_solm(ctx: *mut c_void) -> c_int;
+        pub fn whisper_token_prev(ctx: *mut c_void) -> c_int;
+        pub fn whisper_token_nosp(ctx: *mut c_void) -> c_int;
+        pub fn whisper_token_not(ctx: *mut c_void) -> c_int;
+        pub fn whisper_token_beg(ctx: *mut c_void) -> c_int;
+        pub fn whisper_token_lang(ctx: *mut c_void, lang_id: c_int) -> c_int;
+        pub fn whisper_token_translate(ctx: *mut c_void) -> c_int;
+        pub fn whisper_token_transcribe(ctx: *mut c_void) -> c_int;
+
         // Default params
         pub fn whisper_full_default_params(strategy: c_int) -> WhisperFullParams;
         pub fn whisper_context_default_params() -> WhisperContextParams;
+
+        // Voice activity detection (Silero); segment times are in 10 ms units
+        pub fn whisper_vad_default_context_params() -> WhisperVadContextParams;
+        pub fn whisper_vad_init_from_file_with_params(path_model: *const c_char, params: WhisperVadContextParams) -> *mut c_void;
+        pub fn whisper_vad_segments_from_samples(
+            vctx: *mut c_void,
+            params: WhisperVadParams,
+            samples: *const c_float,
+            n_samples: c_int
+        ) -> *mut c_void;
+        pub fn whisper_vad_segments_n_segments(segments: *mut c_void) -> c_int;
+        pub fn whisper_vad_segments_get_segment_t0(segments: *mut c_void, i_segment: c_int) -> c_float;
+        pub fn whisper_vad_segments_get_segment_t1(segments: *mut c_void, i_segment: c_int) -> c_float;
+        pub fn whisper_vad_free_segments(segments: *mut c_void);
+        pub fn whisper_vad_free(vctx: *mut c_void);
+
+        // Quantization (ggml); `ttype` is an `enum ggml_type`
+        pub fn ggml_quantize_chunk(
+            ttype: c_int,
+            src: *const c_float,
+            dst: *mut c_void,
+            start: i64,
+            nrows: i64,
+            n_per_row: i64,
+            imatrix: *const c_float
+        ) -> usize;
+        pub fn ggml_fp16_to_fp32_row(x: *const u16, y: *mut c_float, n: i64);
     }
 
     #[repr(C)]
@@ -138,6 +352,14 @@ mod ffi {
         pub dtw_mem_size: usize,
     }
 
+    #[repr(C)]
+    pub struct WhisperModelLoader {
+        pub context: *mut c_void,
+        pub read: unsafe extern "C" fn(ctx: *mut c_void, output: *mut c_void, read_size: usize) -> usize,
+        pub eof: unsafe extern "C" fn(ctx: *mut c_void) -> bool,
+        pub close: unsafe extern "C" fn(ctx: *mut c_void),
+    }
+
     #[repr(C)]
     pub struct WhisperAheads {
         pub n_heads: usize,
@@ -150,6 +372,7 @@ mod ffi {
         pub n_head: c_int,
     }
 
+    #[derive(Clone, Copy)]
     #[repr(C)]
     pub struct WhisperFullParams {
         pub strategy: c_int, // enum whisper_sampling_strategy
@@ -241,17 +464,20 @@ mod ffi {
         pub vad_params: WhisperVadParams,
     }
 
+    #[derive(Clone, Copy)]
     #[repr(C)]
     pub struct WhisperGreedyParams {
         pub best_of: c_int,
     }
 
+    #[derive(Clone, Copy)]
     #[repr(C)]
     pub struct WhisperBeamSearchParams {
         pub beam_size: c_int,
         pub patience: f32,
     }
 
+    #[derive(Clone, Copy)]
     #[repr(C)]
     pub struct WhisperVadParams {
         pub threshold: f32,
@@ -261,277 +487,300 @@ mod ffi {
         pub speech_pad_ms: c_int,
         pub samples_overlap: f32,
     }
-}
 
-unsafe impl Send for WhisperContext {}
-unsafe impl Sync for WhisperContext {}
-
-impl Drop for WhisperContext {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.ctx.is_null() {
-                if !self.state.is_null() {
-                    ffi::whisper_free_state(self.state);
-                    self.state = null_mut();
-                }
-                ffi::whisper_free(self.ctx);
-                self.ctx = null_mut();
-            }
-        }
-        self.is_loaded = false;
+    #[repr(C)]
+    pub struct WhisperVadContextParams {
+        pub n_threads: c_int,
+        pub use_gpu: bool,
+        pub gpu_device: c_int,
     }
 }
 
-impl WhisperContext {
-    fn new(model_path: &str) -> Result<Self, WhisperError> {
-        let model_path_c = CString::new(model_path)
-            .map_err(|_| WhisperError::ModelInitError("Invalid model path".to_string()))?;
-
-        let params = unsafe { ffi::whisper_context_default_params() };
-
-        let ctx = unsafe { ffi::whisper_init_from_file_with_params(model_path_c.as_ptr(), params) };
-
-        if ctx.is_null() {
-            return Err(WhisperError::ModelInitError(format!("Failed to load model from {}", model_path)));
-        }
-
-        let state = unsafe { ffi::whisper_init_state(ctx) };
-
-        if state.is_null() {
-            unsafe { ffi::whisper_free(ctx) };
-            return Err(WhisperError::ModelInitError("Failed to initialize state".to_string()));
-        }
-
-        Ok(WhisperContext {
-            ctx,
-            state,
-            is_loaded: true,
-            model_path: model_path.to_string(),
-        })
+/// Read segment `i` of the last `whisper_full_with_state` run on `state`.
+/// whisper.cpp reports times in 10 ms units; they are converted to milliseconds here.
+fn read_segment(ctx: *mut c_void, state: *mut c_void, i: c_int) -> Option<Segment> {
+    let text_ptr = unsafe { ffi::whisper_full_get_segment_text_from_state(state, i) };
+    if text_ptr.is_null() {
+        return None;
     }
 
-    fn process_audio(&mut self, audio_data: &[f32], language: Option<&str>) -> Result<Vec<String>, WhisperError> {
-        if !self.is_loaded || self.ctx.is_null() || self.state.is_null() {
-            return Err(WhisperError::InvalidModel("Model not loaded".to_string()));
-        }
-
-        println!("DEBUG: Starting process_audio with {} samples", audio_data.len());
-
-        // Set up parameters with safer defaults
-        let mut params = unsafe { ffi::whisper_full_default_params(0) }; // 0 = WHISPER_SAMPLING_GREEDY
-
-        // Essential parameters only
-        params.print_realtime = false;
-        params.print_progress = false;
-        params.print_timestamps = true;
-        params.translate = false;
-        params.single_segment = false;
-        params.max_tokens = 0;
-        params.n_threads = 4;
-        
-        // Initialize all pointer fields to null for safety
-        params.language = null_mut();
-        params.initial_prompt = null_mut();
-        params.prompt_tokens = null_mut();
-        params.suppress_regex = null_mut();
-        params.new_segment_callback = null_mut();
-        params.new_segment_callback_user_data = null_mut();
-        params.progress_callback = null_mut();
-        params.progress_callback_user_data = null_mut();
-        params.encoder_begin_callback = null_mut();
-        params.encoder_begin_callback_user_data = null_mut();
-        
-        // Initialize new fields that were missing
-        params.abort_callback = null_mut();
-        params.abort_callback_user_data = null_mut();
-        params.logits_filter_callback = null_mut();
-        params.logits_filter_callback_user_data = null_mut();
-        params.grammar_rules = null_mut();
-        params.n_grammar_rules = 0;
-        params.i_start_rule = 0;
-        params.grammar_penalty = 0.0;
-        params.vad = false;
-        params.vad_model_path = null_mut();
-        
-        // Initialize VAD params to default values
-        params.vad_params = ffi::WhisperVadParams {
-            threshold: 0.5,
-            min_speech_duration_ms: 250,
-            min_silence_duration_ms: 2000,
-            max_speech_duration_s: 30.0,
-            speech_pad_ms: 30,
-            samples_overlap: 0.0,
-        };
-
-        // Set language if provided
-        let lang_c_string: Option<CString> = language.map(|lang| CString::new(lang).unwrap_or_default());
-        if let Some(lang_ptr) = lang_c_string.as_ref() {
-            params.language = lang_ptr.as_ptr();
-            println!("DEBUG: Language set to: {:?}", language);
-        }
-
-        println!("DEBUG: Calling whisper_full_with_state...");
-
-        // Process audio
-        let result = unsafe {
-            ffi::whisper_full_with_state(
-                self.ctx,
-                self.state,
-                params,
-                audio_data.as_ptr(),
-                audio_data.len() as c_int
-            )
-        };
-
-        println!("DEBUG: whisper_full_with_state returned: {}", result);
+    let text = unsafe { CStr::from_ptr(text_ptr) }
+        .to_string_lossy()
+        .to_string();
+
+    let (t0, t1, no_speech_prob, n_tokens) = unsafe {
+        (
+            ffi::whisper_full_get_segment_t0_from_state(state, i),
+            ffi::whisper_full_get_segment_t1_from_state(state, i),
+            ffi::whisper_full_get_segment_no_speech_prob_from_state(state, i),
+            ffi::whisper_full_n_tokens_from_state(state, i),
+        )
+    };
 
-        if result != 0 {
-            return Err(WhisperError::ProcessingError(format!("Failed to process audio: {}", result)));
-        }
+    let tokens = (0..n_tokens)
+        .map(|j| read_token(ctx, state, i, j))
+        .collect();
 
-        println!("DEBUG: Getting number of segments...");
-
-        // Extract results using state-based functions
-        let n_segments = unsafe { ffi::whisper_full_n_segments_from_state(self.state) };
-        println!("DEBUG: Found {} segments", n_segments);
-        
-        let mut segments = Vec::with_capacity(n_segments as usize);
-
-        for i in 0..n_segments {
-            let text_ptr = unsafe { ffi::whisper_full_get_segment_text_from_state(self.state, i) };
-            if !text_ptr.is_null() {
-                let text = unsafe { CStr::from_ptr(text_ptr) }
-                    .to_string_lossy()
-                    .to_string();
-                segments.push(text);
-            }
-        }
+    Some(Segment {
+        text,
+        t0_ms: t0 * 10,
+        t1_ms: t1 * 10,
+        no_speech_prob,
+        tokens,
+    })
+}
 
-        println!("DEBUG: process_audio completed successfully");
-        Ok(segments)
+/// Read token `j` of segment `i`. Ids at or above EOT are whisper's special tokens.
+fn read_token(ctx: *mut c_void, state: *mut c_void, i: c_int, j: c_int) -> TokenData {
+    let data = unsafe { ffi::whisper_full_get_token_data_from_state(state, i, j) };
+    let text_ptr = unsafe { ffi::whisper_full_get_token_text_from_state(ctx, state, i, j) };
+    let bytes = if text_ptr.is_null() {
+        Vec::new()
+    } else {
+        unsafe { CStr::from_ptr(text_ptr) }.to_bytes().to_vec()
+    };
+    let eot = unsafe { ffi::whisper_token_eot(ctx) };
+
+    TokenData {
+        id: data.id,
+        text: String::from_utf8_lossy(&bytes).to_string(),
+        p: data.p,
+        plog: data.plog,
+        t0_ms: data.t0 * 10,
+        t1_ms: data.t1 * 10,
+        t_dtw_ms: (data.t_dtw >= 0).then_some(data.t_dtw * 10),
+        special: data.id >= eot,
+        bytes,
     }
+}
 
-    fn get_model_info(&self) -> Result<String, WhisperError> {
-        if !self.is_loaded || self.ctx.is_null() {
-            return Err(WhisperError::InvalidModel("Model not loaded".to_string()));
-        }
-
-        let info_ptr = unsafe { ffi::whisper_version() };
-        if info_ptr.is_null() {
-            return Err(WhisperError::InternalError("Failed to get model info".to_string()));
-        }
-
-        let info = unsafe { CStr::from_ptr(info_ptr) }
-            .to_string_lossy()
-            .to_string();
-
-        Ok(info)
+fn language_code(lang_id: c_int) -> Option<String> {
+    let code_ptr = unsafe { ffi::whisper_lang_str(lang_id) };
+    if code_ptr.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(code_ptr) }.to_string_lossy().to_string())
     }
+}
 
-    fn is_valid(&self) -> bool {
-        self.is_loaded && !self.ctx.is_null() && !self.state.is_null()
+fn is_known_language(code: &str) -> bool {
+    match CString::new(code) {
+        Ok(code_c) => unsafe { ffi::whisper_lang_id(code_c.as_ptr()) >= 0 },
+        Err(_) => false,
     }
+}
 
-    fn process_audio_sliding_window(
-        &mut self, 
-        audio_data: &[f32], 
-        window_size_sec: f32,
-        step_size_sec: f32,
-        sample_rate: i32,
-        language: Option<&str>
-    ) -> Result<Vec<String>, WhisperError> {
-        if !self.is_loaded || self.ctx.is_null() || self.state.is_null() {
-            return Err(WhisperError::InvalidModel("Model not loaded".to_string()));
-        }
-
-        if window_size_sec <= 0.0 || step_size_sec <= 0.0 || step_size_sec > window_size_sec {
-            return Err(WhisperError::ProcessingError("Invalid window or step size".to_string()));
-        }
+// Exported C API functions. Every function that can fail records why in the
+// calling thread's last error (see `whisper_rust_last_error_code`) and clears it
+// on success. Bodies run under `guard`/`catch_panic`, so a panic is reported as
+// an internal error instead of unwinding into the caller.
 
-        if sample_rate <= 0 {
-            return Err(WhisperError::ProcessingError("Invalid sample rate".to_string()));
-        }
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_init(model_path: *const c_char) -> i32 {
+    guard(|| {
+        let model_path = unsafe { required_str_from_c(model_path, "model_path") }?;
+        init_whisper(model_path)
+    })
+    .unwrap_or(-1)
+}
 
-        let window_samples = (window_size_sec * sample_rate as f32) as usize;
-        let step_samples = (step_size_sec * sample_rate as f32) as usize;
+/// Initialize a model with `ContextOptions` given as JSON (NULL or "" for defaults)
+///
+/// # Safety
+///
+/// `model_path` must point to a NUL-terminated string. `options_json` may be NULL;
+/// otherwise it must point to a NUL-terminated UTF-8 string. Both are only read
+/// during the call. Non-UTF-8 text or invalid JSON fails the call with -1 and
+/// `WHISPER_RUST_ERR_INVALID_PARAMETER`; no model is loaded.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_init_with_options(model_path: *const c_char, options_json: *const c_char) -> i32 {
+    guard(|| {
+        let model_path = unsafe { required_str_from_c(model_path, "model_path") }?;
+        let options: ContextOptions = unsafe { options_from_c(options_json) }?;
+        init_whisper_with_options(model_path, &options)
+    })
+    .unwrap_or(-1)
+}
 
-        if window_samples >= audio_data.len() {
-            // If audio is shorter than one window, process the entire audio
-            return self.process_audio(audio_data, language);
+/// Initialize a model from `buffer_size` bytes at `buffer` (the bytes are only read
+/// during the call), with `ContextOptions` given as JSON (NULL or "" for defaults)
+///
+/// # Safety
+///
+/// `buffer` must point to `buffer_size` readable bytes that stay valid and unchanged
+/// until the call returns; the model keeps no reference to them, so the caller may
+/// free the buffer right after. NULL or a size of 0 fails with
+/// `WHISPER_RUST_ERR_INVALID_PARAMETER`. `options_json` may be NULL; otherwise it
+/// must point to a NUL-terminated UTF-8 string that is only read during the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_init_from_buffer(buffer: *const c_void, buffer_size: usize, options_json: *const c_char) -> i32 {
+    guard(|| {
+        if buffer.is_null() || buffer_size == 0 {
+            return Err(WhisperError::InvalidParameter("Model buffer is NULL or empty".to_string()));
         }
 
-        let mut all_segments = Vec::new();
-        let mut position = 0;
+        let bytes = unsafe { slice::from_raw_parts(buffer as *const u8, buffer_size) };
+        let options: ContextOptions = unsafe { options_from_c(options_json) }?;
+        init_whisper_from_bytes(bytes, &options)
+    })
+    .unwrap_or(-1)
+}
 
-        while position + window_samples <= audio_data.len() {
-            let window = &audio_data[position..position + window_samples];
-            let segments = self.process_audio(window, language)?;
+/// Initialize a model stored at `offset` in the open file `fd` (still owned by the caller)
+///
+/// # Safety
+///
+/// `fd` must be an open, readable file descriptor for the whole call. The library
+/// reads through a duplicate of it: `fd` itself is never closed, and its file
+/// position is left unchanged, so the caller keeps ownership and closes it when
+/// convenient. `offset` is the byte position of the model's first byte in the file
+/// (e.g. `AssetFileDescriptor.getStartOffset()`), not relative to the current
+/// position; the model runs from there to its own end. A negative offset fails
+/// with `WHISPER_RUST_ERR_INVALID_PARAMETER`, an invalid descriptor with
+/// `WHISPER_RUST_ERR_MODEL_INIT`. `options_json` may be NULL; otherwise it must
+/// point to a NUL-terminated UTF-8 string that is only read during the call.
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_init_from_fd(fd: c_int, offset: i64, options_json: *const c_char) -> i32 {
+    guard(|| {
+        let offset = u64::try_from(offset)
+            .map_err(|_| WhisperError::InvalidParameter(format!("Negative model offset {}", offset)))?;
+        let options: ContextOptions = unsafe { options_from_c(options_json) }?;
+        init_whisper_from_fd(fd, offset, &options)
+    })
+    .unwrap_or(-1)
+}
 
-            for segment in segments {
-                all_segments.push(segment);
-            }
+/// Initialize the model named `model_name` in the registry at `models_dir` (see
+/// `ModelRegistry`), after checking it is complete and matches its checksum
+///
+/// # Safety
+///
+/// `models_dir` must point to a NUL-terminated UTF-8 path of the registry directory
+/// and `model_name` to a NUL-terminated UTF-8 name of one of its entries; NULL for
+/// either fails with `WHISPER_RUST_ERR_INVALID_PARAMETER`. `options_json` may be
+/// NULL for the defaults; otherwise it must point to a NUL-terminated UTF-8 string,
+/// and invalid JSON fails with `WHISPER_RUST_ERR_INVALID_PARAMETER`. All three are
+/// only read during the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_init_from_registry(
+    models_dir: *const c_char,
+    model_name: *const c_char,
+    options_json: *const c_char
+) -> i32 {
+    guard(|| {
+        let models_dir = unsafe { required_str_from_c(models_dir, "models_dir") }?;
+        let model_name = unsafe { required_str_from_c(model_name, "model_name") }?;
+        let options: ContextOptions = unsafe { options_from_c(options_json) }?;
+
+        let whisper = ModelRegistry::open(models_dir)?.load(model_name, options)?;
+        Ok(register_instance(whisper.session()))
+    })
+    .unwrap_or(-1)
+}
 
-            position += step_samples;
-        }
+fn register_instance(session: Session) -> i32 {
+    let handle = InstanceHandle {
+        cancellation: session.cancellation_token(),
+        model: Arc::clone(session.model()),
+    };
+    let instance_id = INSTANCES.insert(session);
+    INSTANCE_HANDLES.lock().insert(instance_id, handle);
 
-        // Process the last window if there's remaining audio
-        if position < audio_data.len() && audio_data.len() - position > step_samples / 2 {
-            let window = &audio_data[audio_data.len() - window_samples.min(audio_data.len())..audio_data.len()];
-            let segments = self.process_audio(window, language)?;
+    instance_id
+}
 
-            for segment in segments {
-                all_segments.push(segment);
-            }
-        }
+/// Run `f` on the session behind `instance_id`; only that instance is locked meanwhile
+fn with_session<T>(instance_id: i32, f: impl FnOnce(&mut Session) -> Result<T, WhisperError>) -> Result<T, WhisperError> {
+    let session = INSTANCES.get(instance_id)
+        .ok_or_else(|| WhisperError::InvalidParameter(format!("Unknown instance {}", instance_id)))?;
 
-        Ok(all_segments)
-    }
+    let mut session = session.lock();
+    f(&mut session)
 }
 
-// Exported C API functions
-
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn whisper_rust_init(model_path: *const c_char) -> i32 {
-    let model_path_str = match unsafe { CStr::from_ptr(model_path) }.to_str() {
-        Ok(s) => s,
-        Err(_) => return -1,
-    };
+pub unsafe extern "C" fn whisper_rust_free(instance_id: i32) -> bool {
+    guard(|| free_whisper(instance_id)).is_some()
+}
 
-    match WhisperContext::new(model_path_str) {
-        Ok(context) => {
-            let mut next_id = NEXT_INSTANCE_ID.lock().unwrap();
-            let instance_id = *next_id;
-            *next_id += 1;
+/// Abort the transcription currently running on an instance. The running call
+/// returns false; the segments/with_options variants still write the segments
+/// completed before the abort. The next call on the instance runs normally.
+#[unsafe(no_mangle)]
+pub extern "C" fn whisper_rust_cancel(instance_id: i32) -> bool {
+    guard(|| cancel(instance_id)).is_some()
+}
 
-            let mut instances = INSTANCES.lock().unwrap();
-            instances.insert(instance_id, Arc::new(Mutex::new(context)));
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_is_valid(instance_id: i32) -> bool {
+    catch_panic(false, || is_valid_model(instance_id))
+}
 
-            instance_id
-        },
-        Err(_) => -1,
-    }
+/// `ErrorCode` of the last failed call on this thread, or 0 if the last call succeeded
+#[unsafe(no_mangle)]
+pub extern "C" fn whisper_rust_last_error_code() -> i32 {
+    last_error_code() as i32
 }
 
+/// Message of the last failed call on this thread, or NULL if the last call succeeded.
+/// Owned by the library and valid until the next call on this thread.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn whisper_rust_free(instance_id: i32) -> bool {
-    let mut instances = INSTANCES.lock().unwrap();
-    instances.remove(&instance_id).is_some()
+pub extern "C" fn whisper_rust_last_error_message() -> *const c_char {
+    last_error_message()
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn whisper_rust_is_valid(instance_id: i32) -> bool {
-    let instances = INSTANCES.lock().unwrap();
+pub unsafe extern "C" fn whisper_rust_process_audio(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    language: *const c_char,
+    result_buffer: *mut c_char,
+    result_buffer_size: i32
+) -> bool {
+    guard(|| {
+        check_buffer(result_buffer, result_buffer_size)?;
+        let result = unsafe { c_process_audio(instance_id, audio_data, audio_len, language) }?;
+        unsafe { copy_to_c_buffer(&result, result_buffer, result_buffer_size) }
+    })
+    .is_some()
+}
 
-    if let Some(context) = instances.get(&instance_id) {
-        let context = context.lock().unwrap();
-        context.is_valid()
-    } else {
-        false
-    }
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_process_audio_sliding_window(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    window_size_sec: f32,
+    step_size_sec: f32,
+    sample_rate: i32,
+    language: *const c_char,
+    result_buffer: *mut c_char,
+    result_buffer_size: i32
+) -> bool {
+    guard(|| {
+        check_buffer(result_buffer, result_buffer_size)?;
+        let result = unsafe { c_process_audio_sliding_window(instance_id, audio_data, audio_len, window_size_sec, step_size_sec, sample_rate, language) }?;
+        unsafe { copy_to_c_buffer(&result, result_buffer, result_buffer_size) }
+    })
+    .is_some()
 }
 
+/// Same as `whisper_rust_process_audio`, but writes the segments as a JSON array of
+/// `{ text, t0_ms, t1_ms, no_speech_prob, tokens }` objects into `result_buffer`.
+///
+/// # Safety
+///
+/// `audio_data` must point to `audio_len` readable floats (NULL or `audio_len <= 0`
+/// fails with `WHISPER_RUST_ERR_INVALID_AUDIO`). `language` may be NULL for
+/// auto-detection; otherwise it must point to a NUL-terminated UTF-8 string. Both
+/// are only read during the call. `result_buffer` must point to `result_buffer_size`
+/// writable bytes (NULL or a size <= 0 fails with `WHISPER_RUST_ERR_INVALID_PARAMETER`);
+/// the JSON is written with a trailing NUL, or not at all with
+/// `WHISPER_RUST_ERR_BUFFER_TOO_SMALL` if it does not fit.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn whisper_rust_process_audio(
+pub unsafe extern "C" fn whisper_rust_process_audio_segments(
     instance_id: i32,
     audio_data: *const c_float,
     audio_len: i32,
@@ -539,118 +788,367 @@ pub unsafe extern "C" fn whisper_rust_process_audio(
     result_buffer: *mut c_char,
     result_buffer_size: i32
 ) -> bool {
-    if audio_data.is_null() || audio_len <= 0 || result_buffer.is_null() || result_buffer_size <= 0 {
-        return false;
-    }
+    guard(|| {
+        check_buffer(result_buffer, result_buffer_size)?;
+        let result = unsafe { c_process_audio_segments(instance_id, audio_data, audio_len, language) };
+        unsafe { write_segments(result, result_buffer, result_buffer_size) }
+    })
+    .is_some()
+}
 
-    let instances = INSTANCES.lock().unwrap();
+/// Process audio with `TranscribeOptions` given as JSON (NULL or "" for defaults).
+/// The result is written in the same JSON format as `whisper_rust_process_audio_segments`.
+///
+/// # Safety
+///
+/// `audio_data` must point to `audio_len` readable floats (NULL or `audio_len <= 0`
+/// fails with `WHISPER_RUST_ERR_INVALID_AUDIO`). `options_json` may be NULL for the
+/// defaults; otherwise it must point to a NUL-terminated UTF-8 string, and invalid
+/// JSON fails with `WHISPER_RUST_ERR_INVALID_PARAMETER`. Both are only read during
+/// the call. `result_buffer` must point to `result_buffer_size` writable bytes (NULL
+/// or a size <= 0 fails with `WHISPER_RUST_ERR_INVALID_PARAMETER`); the JSON is
+/// written with a trailing NUL, or not at all with `WHISPER_RUST_ERR_BUFFER_TOO_SMALL`
+/// if it does not fit.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_process_audio_with_options(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    options_json: *const c_char,
+    result_buffer: *mut c_char,
+    result_buffer_size: i32
+) -> bool {
+    guard(|| {
+        check_buffer(result_buffer, result_buffer_size)?;
+        let result = unsafe { c_process_audio_with_options(instance_id, audio_data, audio_len, options_json) };
+        unsafe { write_segments(result, result_buffer, result_buffer_size) }
+    })
+    .is_some()
+}
 
-    let context = match instances.get(&instance_id) {
-        Some(c) => c,
-        None => return false,
-    };
+/// Detect the spoken language. The result is a JSON array of
+/// `{ "language": code, "probability": p }` objects, most likely first.
+///
+/// # Safety
+///
+/// `audio_data` must point to `audio_len` readable floats (NULL or `audio_len <= 0`
+/// fails with `WHISPER_RUST_ERR_INVALID_AUDIO`); it is only read during the call.
+/// `result_buffer` must point to `result_buffer_size` writable bytes (NULL or a size
+/// <= 0 fails with `WHISPER_RUST_ERR_INVALID_PARAMETER`); the JSON is written with a
+/// trailing NUL, or not at all with `WHISPER_RUST_ERR_BUFFER_TOO_SMALL` if it does
+/// not fit.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_detect_language(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    result_buffer: *mut c_char,
+    result_buffer_size: i32
+) -> bool {
+    guard(|| {
+        check_buffer(result_buffer, result_buffer_size)?;
+        let json = unsafe { c_detect_language(instance_id, audio_data, audio_len) }?;
+        unsafe { copy_to_c_buffer(&json, result_buffer, result_buffer_size) }
+    })
+    .is_some()
+}
 
-    let audio_slice = unsafe { slice::from_raw_parts(audio_data, audio_len as usize) };
+// Library-allocated variants of the functions above. They return a NUL-terminated
+// string owned by the caller, or NULL on failure; release it with
+// `whisper_rust_free_string`. There is no size limit on the result.
+
+/// Transcription text, like `whisper_rust_process_audio`
+///
+/// # Safety
+///
+/// `audio_data` must point to `audio_len` readable floats (NULL or `audio_len <= 0`
+/// fails with `WHISPER_RUST_ERR_INVALID_AUDIO`). `language` may be NULL for
+/// auto-detection; otherwise it must point to a NUL-terminated UTF-8 string. Both
+/// are only read during the call. A non-NULL result must be released exactly once
+/// with `whisper_rust_free_string`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_process_audio_alloc(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    language: *const c_char
+) -> *mut c_char {
+    into_c_string(|| unsafe { c_process_audio(instance_id, audio_data, audio_len, language) })
+}
 
-    let language_str = if language.is_null() {
-        None
-    } else {
-        match unsafe { CStr::from_ptr(language) }.to_str() {
-            Ok(s) => Some(s),
-            Err(_) => return false,
-        }
-    };
+/// Transcription text, like `whisper_rust_process_audio_sliding_window`
+///
+/// # Safety
+///
+/// `audio_data` must point to `audio_len` readable floats (NULL or `audio_len <= 0`
+/// fails with `WHISPER_RUST_ERR_INVALID_AUDIO`). `language` may be NULL for
+/// auto-detection; otherwise it must point to a NUL-terminated UTF-8 string. Both
+/// are only read during the call. A non-NULL result must be released exactly once
+/// with `whisper_rust_free_string`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_process_audio_sliding_window_alloc(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    window_size_sec: f32,
+    step_size_sec: f32,
+    sample_rate: i32,
+    language: *const c_char
+) -> *mut c_char {
+    into_c_string(|| unsafe { c_process_audio_sliding_window(instance_id, audio_data, audio_len, window_size_sec, step_size_sec, sample_rate, language) })
+}
 
-    let mut context = context.lock().unwrap();
+/// Segments as JSON, like `whisper_rust_process_audio_segments`. A cancelled or
+/// timed-out call returns NULL; use the buffer variant to get partial segments.
+///
+/// # Safety
+///
+/// `audio_data` must point to `audio_len` readable floats (NULL or `audio_len <= 0`
+/// fails with `WHISPER_RUST_ERR_INVALID_AUDIO`). `language` may be NULL for
+/// auto-detection; otherwise it must point to a NUL-terminated UTF-8 string. Both
+/// are only read during the call. A non-NULL result must be released exactly once
+/// with `whisper_rust_free_string`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_process_audio_segments_alloc(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    language: *const c_char
+) -> *mut c_char {
+    into_c_string(|| {
+        let segments = unsafe { c_process_audio_segments(instance_id, audio_data, audio_len, language) }?;
+        to_json(&segments)
+    })
+}
 
-    match context.process_audio(audio_slice, language_str) {
-        Ok(segments) => {
-            let result = segments.join("\n");
-            let result_c = match CString::new(result) {
-                Ok(s) => s,
-                Err(_) => return false,
-            };
+/// Segments as JSON, like `whisper_rust_process_audio_with_options`
+///
+/// # Safety
+///
+/// `audio_data` must point to `audio_len` readable floats (NULL or `audio_len <= 0`
+/// fails with `WHISPER_RUST_ERR_INVALID_AUDIO`). `options_json` may be NULL for the
+/// defaults; otherwise it must point to a NUL-terminated UTF-8 string, and invalid
+/// JSON fails with `WHISPER_RUST_ERR_INVALID_PARAMETER`. Both are only read during
+/// the call. A non-NULL result must be released exactly once with
+/// `whisper_rust_free_string`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_process_audio_with_options_alloc(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    options_json: *const c_char
+) -> *mut c_char {
+    into_c_string(|| {
+        let segments = unsafe { c_process_audio_with_options(instance_id, audio_data, audio_len, options_json) }?;
+        to_json(&segments)
+    })
+}
 
-            let result_bytes = result_c.as_bytes_with_nul();
-            if result_bytes.len() > result_buffer_size as usize {
-                return false;
-            }
+/// Language probabilities as JSON, like `whisper_rust_detect_language`
+///
+/// # Safety
+///
+/// `audio_data` must point to `audio_len` readable floats (NULL or `audio_len <= 0`
+/// fails with `WHISPER_RUST_ERR_INVALID_AUDIO`); it is only read during the call.
+/// A non-NULL result must be released exactly once with `whisper_rust_free_string`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_detect_language_alloc(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32
+) -> *mut c_char {
+    into_c_string(|| unsafe { c_detect_language(instance_id, audio_data, audio_len) })
+}
 
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    result_bytes.as_ptr(),
-                    result_buffer as *mut u8,
-                    result_bytes.len()
-                );
-            }
+/// Model information as JSON, like `whisper_rust_get_model_info`. Release a
+/// non-NULL result exactly once with `whisper_rust_free_string`.
+#[unsafe(no_mangle)]
+pub extern "C" fn whisper_rust_get_model_info_alloc(instance_id: i32) -> *mut c_char {
+    into_c_string(|| to_json(&get_model_info(instance_id)?))
+}
 
-            true
-        },
-        Err(_) => false,
-    }
+/// Check a model file without loading it. Returns its layout as JSON (`ModelFile`),
+/// or NULL with the reason (e.g. `TruncatedModel`) in the last error.
+///
+/// # Safety
+///
+/// `model_path` must point to a NUL-terminated UTF-8 string, which is only read
+/// during the call; NULL fails with `WHISPER_RUST_ERR_INVALID_PARAMETER`. A non-NULL
+/// result must be released exactly once with `whisper_rust_free_string`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn whisper_rust_inspect_model_alloc(model_path: *const c_char) -> *mut c_char {
+    into_c_string(|| {
+        let model_path = unsafe { required_str_from_c(model_path, "model_path") }?;
+        to_json(&ModelFile::inspect(model_path)?)
+    })
 }
 
+/// Release a string returned by one of the `_alloc` functions. NULL is ignored.
+///
+/// # Safety
+///
+/// `value` must be NULL or a pointer returned by one of the `_alloc` functions of
+/// this library that has not been released yet. Each string must be released
+/// exactly once, and must not be used afterwards. Never pass memory from `malloc`,
+/// another allocator or a caller-supplied buffer.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn whisper_rust_process_audio_sliding_window(
+pub unsafe extern "C" fn whisper_rust_free_string(value: *mut c_char) {
+    if !value.is_null() {
+        catch_panic((), || drop(unsafe { CString::from_raw(value) }));
+    }
+}
+
+// Argument conversion shared by the buffer and `_alloc` variants
+
+unsafe fn c_process_audio(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    language: *const c_char
+) -> Result<String, WhisperError> {
+    let audio = unsafe { audio_from_c(audio_data, audio_len) }?;
+    let language = unsafe { str_from_c(language) }?;
+    process_audio(instance_id, audio, language)
+}
+
+unsafe fn c_process_audio_sliding_window(
     instance_id: i32,
     audio_data: *const c_float,
     audio_len: i32,
     window_size_sec: f32,
     step_size_sec: f32,
     sample_rate: i32,
-    language: *const c_char,
-    result_buffer: *mut c_char,
-    result_buffer_size: i32
-) -> bool {
-    if audio_data.is_null() || audio_len <= 0 || result_buffer.is_null() || result_buffer_size <= 0 {
-        return false;
+    language: *const c_char
+) -> Result<String, WhisperError> {
+    let audio = unsafe { audio_from_c(audio_data, audio_len) }?;
+    let language = unsafe { str_from_c(language) }?;
+    process_audio_sliding_window(instance_id, audio, window_size_sec, step_size_sec, sample_rate, language)
+}
+
+unsafe fn c_process_audio_segments(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    language: *const c_char
+) -> Result<Vec<Segment>, WhisperError> {
+    let audio = unsafe { audio_from_c(audio_data, audio_len) }?;
+    let language = unsafe { str_from_c(language) }?;
+    process_audio_segments(instance_id, audio, language)
+}
+
+unsafe fn c_process_audio_with_options(
+    instance_id: i32,
+    audio_data: *const c_float,
+    audio_len: i32,
+    options_json: *const c_char
+) -> Result<Vec<Segment>, WhisperError> {
+    let audio = unsafe { audio_from_c(audio_data, audio_len) }?;
+    let options: TranscribeOptions = unsafe { options_from_c(options_json) }?;
+    process_audio_with_options(instance_id, audio, &options)
+}
+
+unsafe fn c_detect_language(instance_id: i32, audio_data: *const c_float, audio_len: i32) -> Result<String, WhisperError> {
+    let audio = unsafe { audio_from_c(audio_data, audio_len) }?;
+    let languages = detect_language(instance_id, audio)?;
+
+    Ok(serde_json::Value::Array(
+        languages
+            .into_iter()
+            .map(|(language, probability)| serde_json::json!({ "language": language, "probability": probability }))
+            .collect(),
+    )
+    .to_string())
+}
+
+unsafe fn audio_from_c<'a>(audio_data: *const c_float, audio_len: i32) -> Result<&'a [f32], WhisperError> {
+    if audio_data.is_null() || audio_len <= 0 {
+        return Err(WhisperError::InvalidAudioData);
     }
 
-    let instances = INSTANCES.lock().unwrap();
+    Ok(unsafe { slice::from_raw_parts(audio_data, audio_len as usize) })
+}
+
+/// A NUL-terminated UTF-8 string; NULL becomes `None`
+unsafe fn str_from_c<'a>(value: *const c_char) -> Result<Option<&'a str>, WhisperError> {
+    if value.is_null() {
+        return Ok(None);
+    }
 
-    let context = match instances.get(&instance_id) {
-        Some(c) => c,
-        None => return false,
-    };
+    unsafe { CStr::from_ptr(value) }
+        .to_str()
+        .map(Some)
+        .map_err(|_| WhisperError::InvalidParameter("String argument is not valid UTF-8".to_string()))
+}
 
-    let audio_slice = unsafe { slice::from_raw_parts(audio_data, audio_len as usize) };
+unsafe fn required_str_from_c<'a>(value: *const c_char, name: &str) -> Result<&'a str, WhisperError> {
+    unsafe { str_from_c(value) }?.ok_or_else(|| WhisperError::InvalidParameter(format!("{} is NULL", name)))
+}
 
-    let language_str = if language.is_null() {
-        None
-    } else {
-        match unsafe { CStr::from_ptr(language) }.to_str() {
-            Ok(s) => Some(s),
-            Err(_) => return false,
-        }
+/// Options given as JSON; NULL or "" means the defaults
+unsafe fn options_from_c<T: serde::de::DeserializeOwned + Default>(options_json: *const c_char) -> Result<T, WhisperError> {
+    match unsafe { str_from_c(options_json) }? {
+        None | Some("") => Ok(T::default()),
+        Some(json) => serde_json::from_str(json)
+            .map_err(|e| WhisperError::InvalidParameter(format!("Invalid options JSON: {}", e))),
+    }
+}
+
+fn to_json(value: &impl serde::Serialize) -> Result<String, WhisperError> {
+    serde_json::to_string(value).map_err(|e| WhisperError::InternalError(format!("Failed to serialize result: {}", e)))
+}
+
+/// Write a segment result as JSON. After a cancel or deadline the segments completed
+/// before the abort are written, but the call still reports the abort.
+unsafe fn write_segments(result: Result<Vec<Segment>, WhisperError>, buffer: *mut c_char, buffer_size: i32) -> Result<(), WhisperError> {
+    let segments = match &result {
+        Ok(segments) => Some(segments.as_slice()),
+        Err(e) => e.partial_segments(),
     };
 
-    let mut context = context.lock().unwrap();
+    let written = match segments {
+        Some(segments) => to_json(&segments).and_then(|json| unsafe { copy_to_c_buffer(&json, buffer, buffer_size) }),
+        None => Ok(()),
+    };
 
-    match context.process_audio_sliding_window(audio_slice, window_size_sec, step_size_sec, sample_rate, language_str) {
-        Ok(segments) => {
-            let result = segments.join("\n");
-            let result_c = match CString::new(result) {
-                Ok(s) => s,
-                Err(_) => return false,
-            };
+    result.and(written)
+}
 
-            let result_bytes = result_c.as_bytes_with_nul();
-            if result_bytes.len() > result_buffer_size as usize {
-                return false;
-            }
+/// Hand a result to C as a string the caller frees with `whisper_rust_free_string`.
+/// Errors and panics become NULL and are recorded as the last error.
+fn into_c_string(f: impl FnOnce() -> Result<String, WhisperError>) -> *mut c_char {
+    let value = guard(|| {
+        CString::new(f()?).map_err(|_| WhisperError::InternalError("Result contains a NUL byte".to_string()))
+    });
 
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    result_bytes.as_ptr(),
-                    result_buffer as *mut u8,
-                    result_bytes.len()
-                );
-            }
+    match value {
+        Some(value) => value.into_raw(),
+        None => std::ptr::null_mut(),
+    }
+}
 
-            true
-        },
-        Err(_) => false,
+fn check_buffer(buffer: *mut c_char, buffer_size: i32) -> Result<(), WhisperError> {
+    if buffer.is_null() || buffer_size <= 0 {
+        return Err(WhisperError::InvalidParameter("Result buffer is NULL or empty".to_string()));
     }
+    Ok(())
+}
+
+/// Copy `value` plus a trailing NUL into a caller-provided buffer.
+/// Fails with `BufferTooSmall` (holding the size needed) if it does not fit.
+unsafe fn copy_to_c_buffer(value: &str, buffer: *mut c_char, buffer_size: i32) -> Result<(), WhisperError> {
+    let value_c = CString::new(value)
+        .map_err(|_| WhisperError::InternalError("Result contains a NUL byte".to_string()))?;
+
+    let bytes = value_c.as_bytes_with_nul();
+    if bytes.len() > buffer_size as usize {
+        return Err(WhisperError::BufferTooSmall(bytes.len()));
+    }
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer as *mut u8, bytes.len());
+    }
+
+    Ok(())
 }
 
 #[unsafe(no_mangle)]
@@ -659,30 +1157,32 @@ pub unsafe extern "C" fn whisper_rust_validate_word(
     global_data_words: *const *const c_char,
     global_data_words_len: i32
 ) -> bool {
-    if word.is_null() || global_data_words.is_null() || global_data_words_len <= 0 {
-        return false;
-    }
+    catch_panic(false, || {
+        if word.is_null() || global_data_words.is_null() || global_data_words_len <= 0 {
+            return false;
+        }
 
-    let word_str = match unsafe { CStr::from_ptr(word) }.to_str() {
-        Ok(s) => s.to_lowercase(),
-        Err(_) => return false,
-    };
+        let word_str = match unsafe { CStr::from_ptr(word) }.to_str() {
+            Ok(s) => s,
+            Err(_) => return false,
+        };
 
-    // Convert the array of C strings to Rust strings
-    let mut words = Vec::with_capacity(global_data_words_len as usize);
-    for i in 0..global_data_words_len {
-        let word_ptr = unsafe { *global_data_words.offset(i as isize) };
-        if word_ptr.is_null() {
-            continue;
-        }
+        // Convert the array of C strings to Rust strings
+        let mut words = Vec::with_capacity(global_data_words_len as usize);
+        for i in 0..global_data_words_len {
+            let word_ptr = unsafe { *global_data_words.offset(i as isize) };
+            if word_ptr.is_null() {
+                continue;
+            }
 
-        match unsafe { CStr::from_ptr(word_ptr) }.to_str() {
-            Ok(s) => words.push(s.to_lowercase()),
-            Err(_) => continue,
+            match unsafe { CStr::from_ptr(word_ptr) }.to_str() {
+                Ok(s) => words.push(s),
+                Err(_) => continue,
+            }
         }
-    }
 
-    words.contains(&word_str)
+        validate_word(word_str, &words)
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -691,101 +1191,115 @@ pub unsafe extern "C" fn whisper_rust_get_model_info(
     info_buffer: *mut c_char,
     info_buffer_size: i32
 ) -> bool {
-    if info_buffer.is_null() || info_buffer_size <= 0 {
-        return false;
-    }
+    guard(|| {
+        check_buffer(info_buffer, info_buffer_size)?;
+        let info = to_json(&get_model_info(instance_id)?)?;
+        unsafe { copy_to_c_buffer(&info, info_buffer, info_buffer_size) }
+    })
+    .is_some()
+}
 
-    let instances = INSTANCES.lock().unwrap();
+// Public Rust API (when used as a Rust library). The instance ids are shared with the
+// C API; code that does not need them can use `Whisper` and `Session` directly.
 
-    let context = match instances.get(&instance_id) {
-        Some(c) => c,
-        None => return false,
-    };
+pub fn init_whisper(model_path: &str) -> Result<i32, WhisperError> {
+    init_whisper_with_options(model_path, &ContextOptions::default())
+}
 
-    let context = context.lock().unwrap();
+/// Initialize a model with explicit context options (GPU, DTW word timestamps, ...)
+pub fn init_whisper_with_options(model_path: &str, options: &ContextOptions) -> Result<i32, WhisperError> {
+    let whisper = Whisper::load(model_path, options.clone())?;
+    Ok(register_instance(whisper.session()))
+}
 
-    match context.get_model_info() {
-        Ok(info) => {
-            let info_c = match CString::new(info) {
-                Ok(s) => s,
-                Err(_) => return false,
-            };
+/// Initialize a model from bytes in memory, e.g. a bundled asset or a decrypted download
+pub fn init_whisper_from_bytes(bytes: &[u8], options: &ContextOptions) -> Result<i32, WhisperError> {
+    let whisper = Whisper::load_from_bytes(bytes, options.clone())?;
+    Ok(register_instance(whisper.session()))
+}
 
-            let info_bytes = info_c.as_bytes_with_nul();
-            if info_bytes.len() > info_buffer_size as usize {
-                return false;
-            }
+/// Initialize a model stored at `offset` in an open file descriptor. The
+/// descriptor stays owned by the caller and may be closed once this returns.
+#[cfg(unix)]
+pub fn init_whisper_from_fd(fd: std::os::unix::io::RawFd, offset: u64, options: &ContextOptions) -> Result<i32, WhisperError> {
+    let whisper = Whisper::load_from_fd(fd, offset, options.clone())?;
+    Ok(register_instance(whisper.session()))
+}
 
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    info_bytes.as_ptr(),
-                    info_buffer as *mut u8,
-                    info_bytes.len()
-                );
-            }
+/// Register an instance on an already loaded model. Instances sharing a model
+/// decode in parallel on their own pooled states without reloading the weights.
+pub fn init_whisper_with_model(model: Arc<Model>) -> i32 {
+    register_instance(Whisper::from_model(model).session())
+}
 
-            true
-        },
-        Err(_) => false,
+pub fn free_whisper(instance_id: i32) -> Result<(), WhisperError> {
+    INSTANCE_HANDLES.lock().remove(&instance_id);
+
+    // A call still running on the instance keeps it alive until it returns
+    match INSTANCES.remove(instance_id) {
+        Some(_) => Ok(()),
+        None => Err(WhisperError::InvalidParameter(format!("Unknown instance {}", instance_id))),
     }
 }
 
-// Public Rust API (when used as a Rust library)
-
-pub fn init_whisper(model_path: &str) -> Result<i32, WhisperError> {
-    let model_path = Path::new(model_path);
-    if !model_path.exists() {
-        return Err(WhisperError::ModelInitError(format!("Model file not found: {}", model_path.display())));
+/// Abort the transcription currently running on `instance_id`.
+/// The running call returns `WhisperError::Cancelled` with the segments completed so far.
+pub fn cancel(instance_id: i32) -> Result<(), WhisperError> {
+    match INSTANCE_HANDLES.lock().get(&instance_id) {
+        Some(handle) => {
+            handle.cancellation.cancel();
+            Ok(())
+        }
+        None => Err(WhisperError::InvalidParameter(format!("Unknown instance {}", instance_id))),
     }
+}
 
-    let model_path_c = CString::new(model_path.to_string_lossy().as_bytes())
-        .map_err(|_| WhisperError::ModelInitError("Invalid model path".to_string()))?;
+pub fn is_valid_model(instance_id: i32) -> bool {
+    // Sessions only exist for successfully loaded models, and this must not wait on a running call
+    INSTANCES.get(instance_id).is_some()
+}
 
-    let instance_id = unsafe { whisper_rust_init(model_path_c.as_ptr()) };
+pub fn process_audio(instance_id: i32, audio: &[f32], language: Option<&str>) -> Result<String, WhisperError> {
+    process_audio_segments(instance_id, audio, language).map(|segments| segments_to_text(&segments))
+}
 
-    if instance_id < 0 {
-        Err(WhisperError::ModelInitError("Failed to initialize whisper model".to_string()))
-    } else {
-        Ok(instance_id)
-    }
+/// Transcribe audio and keep the per-segment timing that `process_audio` flattens away
+pub fn process_audio_segments(instance_id: i32, audio: &[f32], language: Option<&str>) -> Result<Vec<Segment>, WhisperError> {
+    process_audio_with_options(instance_id, audio, &TranscribeOptions::for_language(language))
 }
 
-pub fn free_whisper(instance_id: i32) -> Result<(), WhisperError> {
-    if unsafe { whisper_rust_free(instance_id) } {
-        Ok(())
-    } else {
-        Err(WhisperError::InternalError(format!("Failed to free instance {}", instance_id)))
-    }
+/// Transcribe audio with full control over whisper's decoding parameters
+pub fn process_audio_with_options(instance_id: i32, audio: &[f32], options: &TranscribeOptions) -> Result<Vec<Segment>, WhisperError> {
+    with_session(instance_id, |session| session.transcribe(audio, options))
 }
 
-pub fn is_valid_model(instance_id: i32) -> bool {
-    unsafe { whisper_rust_is_valid(instance_id) }
+/// Transcribe audio while reporting progress (0-100) and each segment as soon as it
+/// is decoded. Both closures run on the calling thread, inside the whisper call;
+/// a panic in either is re-raised after the call returns.
+pub fn process_audio_with_callbacks(
+    instance_id: i32,
+    audio: &[f32],
+    options: &TranscribeOptions,
+    on_progress: impl FnMut(u8),
+    on_segment: impl FnMut(&Segment),
+) -> Result<Vec<Segment>, WhisperError> {
+    with_session(instance_id, |session| session.transcribe_with_callbacks(audio, options, on_progress, on_segment))
 }
 
-pub fn process_audio(instance_id: i32, audio: &[f32], language: Option<&str>) -> Result<String, WhisperError> {
-    let buffer_size = 10240; // Adjust as needed
-    let mut result_buffer = vec![0u8; buffer_size];
-
-    let language_c = language.map(|l| CString::new(l).unwrap());
-    let language_ptr = language_c.as_ref().map_or(std::ptr::null(), |c| c.as_ptr());
-
-    let success = unsafe { whisper_rust_process_audio(
-        instance_id,
-        audio.as_ptr(),
-        audio.len() as i32,
-        language_ptr,
-        result_buffer.as_mut_ptr() as *mut c_char,
-        buffer_size as i32
-    ) };
-
-    if success {
-        let result = unsafe { CStr::from_ptr(result_buffer.as_ptr() as *const c_char) }
-            .to_string_lossy()
-            .to_string();
-        Ok(result)
-    } else {
-        Err(WhisperError::ProcessingError("Failed to process audio".to_string()))
-    }
+/// Find the speech regions in `audio` with whisper.cpp's Silero VAD
+pub fn detect_speech_regions(instance_id: i32, audio: &[f32], vad: &VadOptions) -> Result<Vec<SpeechRegion>, WhisperError> {
+    with_session(instance_id, |session| session.detect_speech(audio, vad, TranscribeOptions::default().n_threads))
+}
+
+/// Transcribe with `options.vad` (required) and return the segments together with
+/// the speech regions VAD found. Audio without speech is not decoded at all.
+pub fn process_audio_with_speech_regions(instance_id: i32, audio: &[f32], options: &TranscribeOptions) -> Result<Transcript, WhisperError> {
+    with_session(instance_id, |session| session.transcribe_with_speech_regions(audio, options))
+}
+
+/// Detect the spoken language; returns `(code, probability)` pairs, most likely first
+pub fn detect_language(instance_id: i32, audio: &[f32]) -> Result<Vec<(String, f32)>, WhisperError> {
+    with_session(instance_id, |session| session.detect_language(audio, TranscribeOptions::default().n_threads))
 }
 
 pub fn process_audio_sliding_window(
@@ -796,71 +1310,44 @@ pub fn process_audio_sliding_window(
     sample_rate: i32,
     language: Option<&str>
 ) -> Result<String, WhisperError> {
-    let buffer_size = 102400; // Larger buffer for sliding window results
-    let mut result_buffer = vec![0u8; buffer_size];
-
-    let language_c = language.map(|l| CString::new(l).unwrap());
-    let language_ptr = language_c.as_ref().map_or(std::ptr::null(), |c| c.as_ptr());
-
-    let success = unsafe { whisper_rust_process_audio_sliding_window(
-        instance_id,
-        audio.as_ptr(),
-        audio.len() as i32,
-        window_size_sec,
-        step_size_sec,
-        sample_rate,
-        language_ptr,
-        result_buffer.as_mut_ptr() as *mut c_char,
-        buffer_size as i32
-    ) };
-
-    if success {
-        let result = unsafe { CStr::from_ptr(result_buffer.as_ptr() as *const c_char) }
-            .to_string_lossy()
-            .to_string();
-        Ok(result)
-    } else {
-        Err(WhisperError::ProcessingError("Failed to process audio with sliding window".to_string()))
-    }
+    let options = TranscribeOptions::for_language(language);
+    with_session(instance_id, |session| session.transcribe_sliding_window(audio, window_size_sec, step_size_sec, sample_rate, &options))
+        .map(|segments| segments_to_text(&segments))
+}
+
+/// Log-mel spectrogram of audio through the filter bank of the model behind `instance_id`
+pub fn compute_mel(instance_id: i32, audio: &[f32]) -> Result<Mel, WhisperError> {
+    let model = match INSTANCE_HANDLES.lock().get(&instance_id) {
+        Some(handle) => Arc::clone(&handle.model),
+        None => return Err(WhisperError::InvalidParameter(format!("Unknown instance {}", instance_id))),
+    };
+    log_mel_spectrogram_with(audio, model.mel_filters())
 }
 
-pub fn get_model_info(instance_id: i32) -> Result<String, WhisperError> {
-    let buffer_size = 1024;
-    let mut info_buffer = vec![0u8; buffer_size];
+/// Transcribe a log-mel spectrogram (from `compute_mel` or a custom front-end) instead of audio
+pub fn process_mel_with_options(instance_id: i32, mel: &Mel, options: &TranscribeOptions) -> Result<Vec<Segment>, WhisperError> {
+    with_session(instance_id, |session| session.transcribe_mel(mel, options))
+}
 
-    let success = unsafe { whisper_rust_get_model_info(
-        instance_id,
-        info_buffer.as_mut_ptr() as *mut c_char,
-        buffer_size as i32
-    ) };
+/// Tokenizer over the vocabulary of the model behind `instance_id`; does not wait for a running transcription
+pub fn tokenizer(instance_id: i32) -> Result<Tokenizer, WhisperError> {
+    match INSTANCE_HANDLES.lock().get(&instance_id) {
+        Some(handle) => Ok(Tokenizer::new(Arc::clone(&handle.model))),
+        None => Err(WhisperError::InvalidParameter(format!("Unknown instance {}", instance_id))),
+    }
+}
 
-    if success {
-        let info = unsafe { CStr::from_ptr(info_buffer.as_ptr() as *const c_char) }
-            .to_string_lossy()
-            .to_string();
-        Ok(info)
-    } else {
-        Err(WhisperError::InternalError("Failed to get model info".to_string()))
+/// Architecture, quantization and languages of the model behind `instance_id`
+pub fn get_model_info(instance_id: i32) -> Result<ModelInfo, WhisperError> {
+    match INSTANCE_HANDLES.lock().get(&instance_id) {
+        Some(handle) => Ok(handle.model.info()),
+        None => Err(WhisperError::InvalidParameter(format!("Unknown instance {}", instance_id))),
     }
 }
 
 pub fn validate_word(word: &str, global_data_words: &[&str]) -> bool {
-    let word_c = CString::new(word).unwrap();
-
-    // Convert the Rust string slice to an array of C strings
-    let c_words: Vec<CString> = global_data_words.iter()
-        .map(|w| CString::new(*w).unwrap())
-        .collect();
-
-    let c_word_ptrs: Vec<*const c_char> = c_words.iter()
-        .map(|c| c.as_ptr())
-        .collect();
-
-    unsafe { whisper_rust_validate_word(
-        word_c.as_ptr(),
-        c_word_ptrs.as_ptr(),
-        c_word_ptrs.len() as i32
-    ) }
+    let word = word.to_lowercase();
+    global_data_words.iter().any(|w| w.to_lowercase() == word)
 }
 
 // Export Flutter API
@@ -871,10 +1358,53 @@ pub use quran_integration::*;
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn test_api() {
         // This test is just a placeholder. Real tests would need a model file and audio data.
         assert!(true);
     }
+
+    #[test]
+    fn test_unknown_instance() {
+        assert!(!is_valid_model(-1));
+        assert!(matches!(process_audio(-1, &[0.0; 16], None), Err(WhisperError::InvalidParameter(_))));
+        assert!(matches!(tokenizer(-1), Err(WhisperError::InvalidParameter(_))));
+        assert!(matches!(free_whisper(-1), Err(WhisperError::InvalidParameter(_))));
+        assert!(matches!(cancel(-1), Err(WhisperError::InvalidParameter(_))));
+        assert!(validate_word("Bismillah", &["bismillah", "rahman"]));
+    }
+
+    #[test]
+    fn test_alloc_result_round_trip() {
+        let text = into_c_string(|| Ok("بسم الله".to_string()));
+        assert_eq!(unsafe { CStr::from_ptr(text) }.to_str().unwrap(), "بسم الله");
+        unsafe { whisper_rust_free_string(text) };
+
+        assert!(into_c_string(|| Err(WhisperError::InvalidAudioData)).is_null());
+        assert!(unsafe { whisper_rust_process_audio_alloc(-1, std::ptr::null(), 0, std::ptr::null()) }.is_null());
+    }
+
+    #[test]
+    fn test_c_api_reports_errors_instead_of_panicking() {
+        assert_eq!(unsafe { whisper_rust_init(std::ptr::null()) }, -1);
+        assert_eq!(whisper_rust_last_error_code(), ErrorCode::InvalidParameter as i32);
+        assert_eq!(unsafe { whisper_rust_init_from_buffer(std::ptr::null(), 16, std::ptr::null()) }, -1);
+        assert_eq!(whisper_rust_last_error_code(), ErrorCode::InvalidParameter as i32);
+
+        let mut buffer = [0 as c_char; 4];
+        let written = unsafe { copy_to_c_buffer("Al-Fatiha", buffer.as_mut_ptr(), buffer.len() as i32) };
+        assert!(matches!(written, Err(WhisperError::BufferTooSmall(10))));
+
+        // Interior NUL and a panic in the body both come back as NULL plus an internal error
+        assert!(into_c_string(|| Ok("a\0b".to_string())).is_null());
+        assert_eq!(whisper_rust_last_error_code(), ErrorCode::Internal as i32);
+        assert!(into_c_string(|| panic!("unexpected state")).is_null());
+        assert_eq!(whisper_rust_last_error_code(), ErrorCode::Internal as i32);
+
+        assert!(!unsafe { whisper_rust_free(-1) });
+        assert!(!whisper_rust_last_error_message().is_null());
+    }
 }
 
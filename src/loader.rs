@@ -0,0 +1,91 @@
+//! `whisper_model_loader` over a Rust reader, for models that don't live in a file path
+
+use std::ffi::c_void;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use crate::ffi;
+
+/// User data behind the loader callbacks. whisper.cpp calls them on the loading
+/// thread and only during `whisper_init_with_params_no_state`.
+pub(crate) struct ReaderLoader<R> {
+    reader: R,
+    eof: bool,
+}
+
+impl<R: Read> ReaderLoader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self { reader, eof: false }
+    }
+
+    /// The loader struct to pass to whisper.cpp; it borrows `self` for the call
+    pub(crate) fn as_ffi(&mut self) -> ffi::WhisperModelLoader {
+        ffi::WhisperModelLoader {
+            context: self as *mut Self as *mut c_void,
+            read: read_callback::<R>,
+            eof: eof_callback::<R>,
+            // The reader is owned and dropped on the Rust side
+            close: close_callback,
+        }
+    }
+
+    fn read_into(&mut self, output: &mut [u8]) -> usize {
+        let mut filled = 0;
+        while filled < output.len() {
+            match self.reader.read(&mut output[filled..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                // A short read makes whisper.cpp fail the load
+                Err(_) => break,
+            }
+        }
+        filled
+    }
+}
+
+unsafe extern "C" fn read_callback<R: Read>(context: *mut c_void, output: *mut c_void, read_size: usize) -> usize {
+    let Some(loader) = (unsafe { (context as *mut ReaderLoader<R>).as_mut() }) else {
+        return 0;
+    };
+    if output.is_null() {
+        return 0;
+    }
+
+    let output = unsafe { std::slice::from_raw_parts_mut(output as *mut u8, read_size) };
+    // A panic must not unwind through whisper.cpp; report it as a failed read
+    panic::catch_unwind(AssertUnwindSafe(|| loader.read_into(output))).unwrap_or(0)
+}
+
+unsafe extern "C" fn eof_callback<R: Read>(context: *mut c_void) -> bool {
+    match unsafe { (context as *const ReaderLoader<R>).as_ref() } {
+        Some(loader) => loader.eof,
+        None => true,
+    }
+}
+
+unsafe extern "C" fn close_callback(_context: *mut c_void) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_fill_the_request_until_eof() {
+        // Chained readers hand out data in pieces smaller than the request
+        let data = (&[1u8, 2, 3][..]).chain(&[4u8, 5][..]);
+        let mut loader = ReaderLoader::new(data);
+        let ffi_loader = loader.as_ffi();
+
+        let mut output = [0u8; 4];
+        let n = unsafe { (ffi_loader.read)(ffi_loader.context, output.as_mut_ptr() as *mut c_void, 4) };
+        assert_eq!((n, output), (4, [1, 2, 3, 4]));
+        assert!(!unsafe { (ffi_loader.eof)(ffi_loader.context) });
+
+        let n = unsafe { (ffi_loader.read)(ffi_loader.context, output.as_mut_ptr() as *mut c_void, 4) };
+        assert_eq!(n, 1);
+        assert!(unsafe { (ffi_loader.eof)(ffi_loader.context) });
+    }
+}
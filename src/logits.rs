@@ -0,0 +1,198 @@
+//! Logits filters: adjust whisper's token scores from Rust before each token is sampled
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::ffi::{c_float, c_int, c_void};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use parking_lot::Mutex;
+use crate::{ffi, TokenId};
+
+/// Hook into every decoding step (`whisper_logits_filter_callback`).
+///
+/// `tokens` are the tokens decoded so far in the current segment, timestamps
+/// included; `logits` holds one score per vocabulary entry (see `Tokenizer`).
+/// Setting a score to `f32::NEG_INFINITY` rules that token out. With beam search
+/// or `best_of` the filter may run for several decoders at once, hence `&self`.
+pub trait LogitsFilter: Send + Sync {
+    fn filter(&self, tokens: &[TokenId], logits: &mut [f32]);
+}
+
+impl<F> LogitsFilter for F
+where
+    F: Fn(&[TokenId], &mut [f32]) + Send + Sync,
+{
+    fn filter(&self, tokens: &[TokenId], logits: &mut [f32]) {
+        self(tokens, logits)
+    }
+}
+
+/// A logits filter that can sit in `TranscribeOptions`. Clones share the filter
+/// and compare equal; separately created ones never do.
+#[derive(Clone)]
+pub struct SharedLogitsFilter(Arc<dyn LogitsFilter>);
+
+impl SharedLogitsFilter {
+    pub fn new(filter: impl LogitsFilter + 'static) -> Self {
+        Self(Arc::new(filter))
+    }
+}
+
+impl fmt::Debug for SharedLogitsFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedLogitsFilter(..)")
+    }
+}
+
+impl PartialEq for SharedLogitsFilter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Fixed per-token bias: suppress tokens (e.g. Latin script when decoding Arabic)
+/// or make them more likely (hot words, the expected ayah)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenBias {
+    bias: HashMap<TokenId, f32>,
+}
+
+impl TokenBias {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Never emit these tokens
+    pub fn suppress(mut self, tokens: impl IntoIterator<Item = TokenId>) -> Self {
+        self.bias.extend(tokens.into_iter().map(|token| (token, f32::NEG_INFINITY)));
+        self
+    }
+
+    /// Add `amount` to the scores of these tokens; logits are log-odds, so 1.0-5.0 is a strong push
+    pub fn boost(mut self, tokens: impl IntoIterator<Item = TokenId>, amount: f32) -> Self {
+        for token in tokens {
+            *self.bias.entry(token).or_insert(0.0) += amount;
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bias.is_empty()
+    }
+}
+
+impl LogitsFilter for TokenBias {
+    fn filter(&self, _tokens: &[TokenId], logits: &mut [f32]) {
+        for (&token, &bias) in &self.bias {
+            if let Some(logit) = usize::try_from(token).ok().and_then(|i| logits.get_mut(i)) {
+                *logit += bias;
+            }
+        }
+    }
+}
+
+/// User data behind `logits_filter_callback` for one `whisper_full_with_state` call
+pub(crate) struct LogitsFilterRun {
+    filter: SharedLogitsFilter,
+    // A panic must not unwind through whisper.cpp; it is re-raised once the call returns
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl LogitsFilterRun {
+    pub(crate) fn new(filter: SharedLogitsFilter) -> Self {
+        Self {
+            filter,
+            panic: Mutex::new(None),
+        }
+    }
+
+    /// Re-raise a panic caught inside the filter
+    pub(crate) fn resume_panic(&self) {
+        if let Some(payload) = self.panic.lock().take() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn run(&self, tokens: &[TokenId], logits: &mut [f32]) {
+        // After a panic the filter may be in a broken state; stop calling it
+        if self.panic.lock().is_some() {
+            return;
+        }
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.filter.0.filter(tokens, logits))) {
+            self.panic.lock().get_or_insert(payload);
+        }
+    }
+}
+
+/// `whisper_logits_filter_callback`: `logits` has one entry per vocabulary token
+pub(crate) unsafe extern "C" fn logits_filter_callback(
+    ctx: *mut c_void,
+    _state: *mut c_void,
+    tokens: *const ffi::WhisperTokenData,
+    n_tokens: c_int,
+    logits: *mut c_float,
+    user_data: *mut c_void,
+) {
+    let Some(run) = (unsafe { (user_data as *const LogitsFilterRun).as_ref() }) else {
+        return;
+    };
+    if logits.is_null() {
+        return;
+    }
+
+    let history: Vec<TokenId> = if tokens.is_null() || n_tokens <= 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(tokens, n_tokens as usize) }.iter().map(|token| token.id).collect()
+    };
+    let n_vocab = unsafe { ffi::whisper_n_vocab(ctx) }.max(0) as usize;
+    let logits = unsafe { std::slice::from_raw_parts_mut(logits, n_vocab) };
+
+    run.run(&history, logits);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bias_and_closures() {
+        let bias = TokenBias::new().suppress([1]).boost([2, 2, 99], 1.5);
+        let mut logits = [0.5f32; 4];
+        bias.filter(&[], &mut logits);
+        assert_eq!(logits, [0.5, f32::NEG_INFINITY, 3.5, 0.5]);
+
+        // A closure sees the decoded history
+        let only_after_first = |tokens: &[TokenId], logits: &mut [f32]| {
+            if tokens.is_empty() {
+                logits[0] = f32::NEG_INFINITY;
+            }
+        };
+        let filter = SharedLogitsFilter::new(only_after_first);
+        assert_eq!(filter, filter.clone());
+        assert_ne!(filter, SharedLogitsFilter::new(TokenBias::new()));
+
+        let run = LogitsFilterRun::new(filter);
+        let mut logits = [0.0f32; 2];
+        run.run(&[7], &mut logits);
+        assert_eq!(logits, [0.0, 0.0]);
+        run.run(&[], &mut logits);
+        assert_eq!(logits, [f32::NEG_INFINITY, 0.0]);
+    }
+
+    #[test]
+    fn test_panic_is_caught_and_resumed() {
+        let calls = Arc::new(Mutex::new(0));
+        let counted = Arc::clone(&calls);
+        let run = LogitsFilterRun::new(SharedLogitsFilter::new(move |_: &[TokenId], _: &mut [f32]| {
+            *counted.lock() += 1;
+            panic!("filter bug");
+        }));
+
+        run.run(&[], &mut [0.0]);
+        run.run(&[], &mut [0.0]);
+        assert_eq!(*calls.lock(), 1);
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| run.resume_panic())).is_err());
+    }
+}
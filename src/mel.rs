@@ -0,0 +1,322 @@
+//! Log-mel spectrograms, computed the way whisper.cpp computes them before encoding
+//!
+//! `log_mel_spectrogram` follows `whisper_pcm_to_mel`: a 400-sample Hann window
+//! every 160 samples (one frame per 10 ms at 16 kHz), the Slaney mel filters the
+//! model files store, log10 and whisper's 8-decade dynamic range clamp.
+//! `Session::compute_mel` uses the filter bank read from the loaded model file,
+//! so its output is what whisper.cpp computes for the same audio.
+//! `Session::transcribe_mel` decodes a spectrogram through `whisper_set_mel_with_state`,
+//! so spectrograms can be cached, sliced for overlapping windows or come from a
+//! different front-end.
+
+use std::f32::consts::PI;
+use std::io::Read;
+use std::ops::Range;
+use serde::{Deserialize, Serialize};
+use crate::fft::Fft;
+use crate::WhisperError;
+
+pub const SAMPLE_RATE: usize = 16000;
+pub const N_FFT: usize = 400;
+pub const HOP_LENGTH: usize = 160;
+/// Frames the encoder sees per 30 s window
+pub const CHUNK_FRAMES: usize = 3000;
+
+/// A log-mel spectrogram, normalized like whisper.cpp's (roughly -1.5 to 1.5)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mel {
+    pub n_mel: usize,
+    pub n_frames: usize,
+    /// `n_mel` rows of `n_frames` values, band by band; the layout `whisper_set_mel` takes
+    pub data: Vec<f32>,
+}
+
+impl Mel {
+    pub fn new(n_mel: usize, n_frames: usize, data: Vec<f32>) -> Result<Self, WhisperError> {
+        if n_mel == 0 || n_frames == 0 || data.len() != n_mel * n_frames {
+            return Err(WhisperError::InvalidParameter(format!(
+                "Mel data has {} values, expected {} bands x {} frames",
+                data.len(),
+                n_mel,
+                n_frames
+            )));
+        }
+        Ok(Self { n_mel, n_frames, data })
+    }
+
+    /// Values of one mel band over time
+    pub fn band(&self, band: usize) -> &[f32] {
+        &self.data[band * self.n_frames..(band + 1) * self.n_frames]
+    }
+
+    pub fn duration_ms(&self) -> i64 {
+        (self.n_frames * 1000 / (SAMPLE_RATE / HOP_LENGTH)) as i64
+    }
+
+    /// Copy of frames `range`; frame `i` covers the audio from `i * 10` ms
+    pub fn frames(&self, range: Range<usize>) -> Result<Mel, WhisperError> {
+        if range.start >= range.end || range.end > self.n_frames {
+            return Err(WhisperError::InvalidParameter(format!(
+                "Frames {:?} are outside a spectrogram of {} frames",
+                range, self.n_frames
+            )));
+        }
+
+        let data = (0..self.n_mel).flat_map(|band| &self.band(band)[range.clone()]).copied().collect();
+        Mel::new(self.n_mel, range.len(), data)
+    }
+
+    /// The frames followed by a 30 s window of the lowest value, which stands in
+    /// for the silence whisper.cpp appends to audio before encoding
+    pub(crate) fn padded(&self) -> Vec<f32> {
+        let floor = self.data.iter().copied().fold(f32::INFINITY, f32::min);
+        let n_len = self.n_frames + CHUNK_FRAMES;
+
+        let mut data = vec![floor; self.n_mel * n_len];
+        for band in 0..self.n_mel {
+            data[band * n_len..band * n_len + self.n_frames].copy_from_slice(self.band(band));
+        }
+        data
+    }
+}
+
+/// Mel filter bank: `n_mel` rows of `N_FFT / 2 + 1` weights, one per FFT bin
+#[derive(Debug, Clone, PartialEq)]
+pub struct MelFilters {
+    pub n_mel: usize,
+    pub data: Vec<f32>,
+}
+
+impl MelFilters {
+    /// Slaney-scale filters, as `librosa.filters.mel(sr=16000, n_fft=400, n_mels=n_mel)`
+    /// computes them; the model files store these, rounded to f32
+    pub fn slaney(n_mel: usize) -> Self {
+        Self { n_mel, data: mel_filters(n_mel) }
+    }
+
+    /// Read the filter bank a model file stores right after its hyperparameters
+    pub(crate) fn read(mut reader: impl Read) -> Result<Self, WhisperError> {
+        let read_error = |e: std::io::Error| WhisperError::ModelInitError(format!("Failed to read model mel filters: {}", e));
+
+        let mut sizes = [0u8; 8];
+        reader.read_exact(&mut sizes).map_err(read_error)?;
+        let n_mel = i32::from_le_bytes(sizes[..4].try_into().unwrap());
+        let n_bins = i32::from_le_bytes(sizes[4..].try_into().unwrap());
+        if !(1..=256).contains(&n_mel) || n_bins as usize != N_FFT / 2 + 1 {
+            return Err(WhisperError::InvalidModel(format!(
+                "Mel filters are {} x {}, expected {} FFT bins",
+                n_mel,
+                n_bins,
+                N_FFT / 2 + 1
+            )));
+        }
+
+        let mut bytes = vec![0u8; n_mel as usize * n_bins as usize * 4];
+        reader.read_exact(&mut bytes).map_err(read_error)?;
+        let data = bytes.chunks_exact(4).map(|value| f32::from_le_bytes(value.try_into().unwrap())).collect();
+        Ok(Self { n_mel: n_mel as usize, data })
+    }
+
+    /// The bytes `read` consumed
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let n_bins = (N_FFT / 2 + 1) as i32;
+        [self.n_mel as i32, n_bins]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .chain(self.data.iter().flat_map(|value| value.to_le_bytes()))
+            .collect()
+    }
+}
+
+/// Log-mel spectrogram of 16 kHz mono audio with `n_mel` bands (80, or 128 for large-v3),
+/// using Slaney filters computed here; `Session::compute_mel` uses the model's own
+pub fn log_mel_spectrogram(audio: &[f32], n_mel: usize) -> Result<Mel, WhisperError> {
+    if n_mel == 0 {
+        return Err(WhisperError::InvalidParameter("n_mel must be positive".to_string()));
+    }
+    log_mel_spectrogram_with(audio, &MelFilters::slaney(n_mel))
+}
+
+/// Log-mel spectrogram of 16 kHz mono audio through a given filter bank
+pub fn log_mel_spectrogram_with(audio: &[f32], filters: &MelFilters) -> Result<Mel, WhisperError> {
+    if audio.is_empty() {
+        return Err(WhisperError::InvalidAudioData);
+    }
+    let (n_mel, n_bins) = (filters.n_mel, N_FFT / 2 + 1);
+    if n_mel == 0 || filters.data.len() != n_mel * n_bins {
+        return Err(WhisperError::InvalidParameter(format!(
+            "Mel filters hold {} weights, expected {} bands x {} bins",
+            filters.data.len(),
+            n_mel,
+            n_bins
+        )));
+    }
+
+    let n_samples = audio.len();
+    let half = N_FFT / 2;
+
+    // Reflect N_FFT / 2 samples at the start; whisper.cpp also appends 30 s of
+    // zeros, whose frames all come out as log10(1e-10)
+    let mut padded = vec![0f32; half + n_samples + N_FFT + half];
+    for (i, sample) in padded[..half].iter_mut().enumerate() {
+        *sample = audio.get(half - i).copied().unwrap_or(0.0);
+    }
+    padded[half..half + n_samples].copy_from_slice(audio);
+
+    let n_frames = (1 + (n_samples as i64 - half as i64) / HOP_LENGTH as i64) as usize;
+    let n_computed = (n_samples + half - 1) / HOP_LENGTH + 1;
+
+    let hann = hann_window();
+    let mut fft = Fft::new(N_FFT);
+
+    let mut log_mel = vec![0f32; n_mel * n_computed];
+    let (mut re, mut im) = ([0f32; N_FFT], [0f32; N_FFT]);
+    let mut power = [0f32; N_FFT / 2 + 1];
+    for i in 0..n_computed {
+        let offset = i * HOP_LENGTH;
+        for (j, value) in re.iter_mut().enumerate() {
+            *value = hann[j] * padded[offset + j];
+        }
+        im.fill(0.0);
+
+        fft.process(&mut re, &mut im);
+        for (k, value) in power.iter_mut().enumerate() {
+            *value = re[k] * re[k] + im[k] * im[k];
+        }
+
+        for band in 0..n_mel {
+            let weights = &filters.data[band * n_bins..(band + 1) * n_bins];
+            let sum: f64 = power.iter().zip(weights).map(|(&p, &w)| p as f64 * w as f64).sum();
+            log_mel[band * n_computed + i] = sum.max(1e-10).log10() as f32;
+        }
+    }
+
+    // The zero padding contributes -10 to the maximum
+    let max = log_mel.iter().copied().fold(-10f32, f32::max);
+    let floor = max - 8.0;
+
+    let mut data = Vec::with_capacity(n_mel * n_frames);
+    for band in 0..n_mel {
+        let row = &log_mel[band * n_computed..band * n_computed + n_frames];
+        data.extend(row.iter().map(|&value| (value.max(floor) + 4.0) / 4.0));
+    }
+
+    Mel::new(n_mel, n_frames, data)
+}
+
+/// Slaney-scale mel filter bank, as `librosa.filters.mel(sr=16000, n_fft=400, n_mels=n_mel)`
+/// produces and the model files store: `n_mel` rows of `N_FFT / 2 + 1` weights
+fn mel_filters(n_mel: usize) -> Vec<f32> {
+    let n_bins = N_FFT / 2 + 1;
+    let max_mel = hz_to_mel(SAMPLE_RATE as f64 / 2.0);
+    let edges: Vec<f64> = (0..n_mel + 2)
+        .map(|i| mel_to_hz(max_mel * i as f64 / (n_mel + 1) as f64))
+        .collect();
+
+    let mut filters = vec![0f32; n_mel * n_bins];
+    for band in 0..n_mel {
+        let (lower, center, upper) = (edges[band], edges[band + 1], edges[band + 2]);
+        let norm = 2.0 / (upper - lower);
+        for bin in 0..n_bins {
+            let hz = bin as f64 * SAMPLE_RATE as f64 / N_FFT as f64;
+            let rising = (hz - lower) / (center - lower);
+            let falling = (upper - hz) / (upper - center);
+            filters[band * n_bins + bin] = (rising.min(falling).max(0.0) * norm) as f32;
+        }
+    }
+    filters
+}
+
+// Slaney's mel scale: linear below 1 kHz, logarithmic above
+const MEL_HZ_PER_MEL: f64 = 200.0 / 3.0;
+const MEL_LOG_HZ: f64 = 1000.0;
+const MEL_LOG_MEL: f64 = MEL_LOG_HZ / MEL_HZ_PER_MEL;
+
+fn mel_log_step() -> f64 {
+    6.4f64.ln() / 27.0
+}
+
+fn hz_to_mel(hz: f64) -> f64 {
+    if hz < MEL_LOG_HZ {
+        hz / MEL_HZ_PER_MEL
+    } else {
+        MEL_LOG_MEL + (hz / MEL_LOG_HZ).ln() / mel_log_step()
+    }
+}
+
+fn mel_to_hz(mel: f64) -> f64 {
+    if mel < MEL_LOG_MEL {
+        mel * MEL_HZ_PER_MEL
+    } else {
+        MEL_LOG_HZ * (mel_log_step() * (mel - MEL_LOG_MEL)).exp()
+    }
+}
+
+/// Periodic Hann window of N_FFT samples, as whisper.cpp uses
+fn hann_window() -> [f32; N_FFT] {
+    let mut hann = [0f32; N_FFT];
+    for (i, value) in hann.iter_mut().enumerate() {
+        *value = 0.5 * (1.0 - (2.0 * PI * i as f32 / N_FFT as f32).cos());
+    }
+    hann
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_match_librosa() {
+        let filters = mel_filters(80);
+        // librosa.filters.mel(sr=16000, n_fft=400)[0, :3]
+        assert_eq!(filters[0], 0.0);
+        assert!((filters[1] - 0.024_862_6).abs() < 1e-6, "{}", filters[1]);
+        assert_eq!(filters[2], 0.0);
+        assert_eq!(mel_filters(128).len(), 128 * 201);
+    }
+
+    #[test]
+    fn test_spectrogram_of_a_tone() {
+        // One second of a 1 kHz tone
+        let audio: Vec<f32> = (0..SAMPLE_RATE)
+            .map(|i| (2.0 * PI * 1000.0 * i as f32 / SAMPLE_RATE as f32).sin() * 0.5)
+            .collect();
+
+        let mel = log_mel_spectrogram(&audio, 80).unwrap();
+        assert_eq!((mel.n_mel, mel.n_frames), (80, 99));
+        let max = mel.data.iter().copied().fold(f32::MIN, f32::max);
+        assert!(mel.data.iter().all(|&value| value >= max - 2.0 - 1e-6));
+
+        // The loudest band of a middle frame is centred on 1 kHz
+        let loudest = (0..80).max_by(|&a, &b| mel.band(a)[50].total_cmp(&mel.band(b)[50])).unwrap();
+        let center = mel_to_hz(hz_to_mel(8000.0) * (loudest + 1) as f64 / 81.0);
+        assert!((center - 1000.0).abs() < 50.0, "band {} at {} Hz", loudest, center);
+
+        let window = mel.frames(10..20).unwrap();
+        assert_eq!((window.n_frames, window.band(3)), (10, &mel.band(3)[10..20]));
+        assert_eq!(window.duration_ms(), 100);
+        assert!(mel.frames(90..100).is_err());
+        assert!(Mel::new(80, 2, vec![0.0; 10]).is_err());
+        assert!(matches!(log_mel_spectrogram(&[], 80), Err(WhisperError::InvalidAudioData)));
+    }
+
+    #[test]
+    fn test_filters_read_from_a_model_file() {
+        let filters = MelFilters::slaney(80);
+        let read = MelFilters::read(filters.to_bytes().as_slice()).unwrap();
+        assert_eq!(read, filters);
+
+        // The spectrogram follows the filter bank it is given
+        let audio: Vec<f32> = (0..SAMPLE_RATE / 10).map(|i| (i as f32 * 0.3).sin() * 0.5).collect();
+        let slaney = log_mel_spectrogram(&audio, 80).unwrap();
+        assert_eq!(log_mel_spectrogram_with(&audio, &read).unwrap(), slaney);
+        let flat = MelFilters { n_mel: 80, data: vec![0.01; 80 * 201] };
+        assert_ne!(log_mel_spectrogram_with(&audio, &flat).unwrap(), slaney);
+
+        let mut wrong_bins = filters.to_bytes();
+        wrong_bins[4..8].copy_from_slice(&200i32.to_le_bytes());
+        assert!(matches!(MelFilters::read(wrong_bins.as_slice()), Err(WhisperError::InvalidModel(_))));
+        assert!(matches!(MelFilters::read(&filters.to_bytes()[..100]), Err(WhisperError::ModelInitError(_))));
+        assert!(log_mel_spectrogram_with(&audio, &MelFilters { n_mel: 80, data: vec![0.0; 10] }).is_err());
+    }
+}
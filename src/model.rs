@@ -0,0 +1,512 @@
+//! Loaded whisper models, shared between instances
+//!
+//! The weights (`whisper_context`) are loaded once per path and context options
+//! and shared through an `Arc`. Decoding happens on `whisper_state`s taken from
+//! a per-model pool, so any number of instances and threads can decode in
+//! parallel against a single copy of the model.
+
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void, CStr, CString};
+use std::fmt;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use parking_lot::Mutex;
+use log::warn;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use crate::loader::ReaderLoader;
+use crate::model_file::{ModelFile, HEADER_SIZE};
+use crate::{ffi, language_code, AlignmentHeadsPreset, ContextOptions, MelFilters, WhisperError};
+
+type ModelKey = (String, ContextOptions);
+
+// Models currently loaded, so loading the same file twice shares the weights.
+// Each key has its own slot, locked while that model loads, so a slow load only
+// holds up other loads of the same model.
+type ModelSlot = Arc<Mutex<Weak<Model>>>;
+
+static MODEL_CACHE: Lazy<Mutex<HashMap<ModelKey, ModelSlot>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A loaded model. States are created on demand and reused.
+pub struct Model {
+    ctx: *mut c_void,
+    model_path: String,
+    // Unknown for models streamed from a reader or file descriptor
+    file_size: Option<u64>,
+    options: ContextOptions,
+    // The filter bank stored in the model file, which whisper.cpp computes its mel with
+    mel_filters: MelFilters,
+    idle_states: Mutex<Vec<StatePtr>>,
+    // States beyond this many are freed when they are released instead of pooled
+    max_idle_states: AtomicUsize,
+}
+
+/// Idle decoding states a model keeps by default; each holds its own KV cache
+pub const DEFAULT_MAX_IDLE_STATES: usize = 2;
+
+// whisper_context is read-only once loaded; all mutable decoding data lives in the states
+unsafe impl Send for Model {}
+unsafe impl Sync for Model {}
+
+struct StatePtr(*mut c_void);
+
+unsafe impl Send for StatePtr {}
+
+impl Model {
+    /// Load a model, or share the one already loaded from the same path with the same options
+    pub fn load(model_path: &str, options: &ContextOptions) -> Result<Arc<Model>, WhisperError> {
+        let key = (model_path.to_string(), options.clone());
+        let slot = Arc::clone(MODEL_CACHE.lock().entry(key).or_default());
+
+        let result = {
+            let mut loaded = slot.lock();
+            match loaded.upgrade() {
+                Some(model) => Ok(model),
+                None => Self::load_uncached(model_path, options).map(|model| {
+                    let model = Arc::new(model);
+                    *loaded = Arc::downgrade(&model);
+                    model
+                }),
+            }
+        };
+
+        drop(slot);
+        // A slot only we hold can't be locked; drop those whose model is gone
+        MODEL_CACHE
+            .lock()
+            .retain(|_, slot| Arc::strong_count(slot) > 1 || slot.lock().strong_count() > 0);
+        result
+    }
+
+    fn load_uncached(model_path: &str, options: &ContextOptions) -> Result<Model, WhisperError> {
+        let model_path_c = CString::new(model_path)
+            .map_err(|_| WhisperError::ModelInitError("Invalid model path".to_string()))?;
+
+        // Catch truncated or foreign files before whisper.cpp tries to load them
+        ModelFile::inspect(model_path)?;
+        let mel_filters = read_mel_filters(model_path)?;
+
+        let params = context_params(options, model_path, || AlignmentHeadsPreset::detect(model_path));
+
+        // States come from the pool, so don't let whisper.cpp allocate a default one
+        let ctx = unsafe { ffi::whisper_init_from_file_with_params_no_state(model_path_c.as_ptr(), params) };
+        let file_size = std::fs::metadata(model_path).ok().map(|metadata| metadata.len());
+        Self::from_ctx(ctx, model_path.to_string(), file_size, options, mel_filters)
+    }
+
+    /// Load a model held in memory, e.g. a bundled asset or a decrypted download.
+    /// The bytes are only read during the call. Models loaded this way are not
+    /// shared by `load`; clone the returned `Arc` to share them.
+    pub fn load_from_bytes(bytes: &[u8], options: &ContextOptions) -> Result<Arc<Model>, WhisperError> {
+        if bytes.is_empty() {
+            return Err(WhisperError::ModelInitError("Model buffer is empty".to_string()));
+        }
+        ModelFile::inspect_bytes(bytes)?;
+        let mel_filters = MelFilters::read(&bytes[HEADER_SIZE..])?;
+
+        let source = "<memory>";
+        let params = context_params(options, source, || AlignmentHeadsPreset::detect_from_header(bytes));
+
+        // whisper.cpp only reads the buffer, despite taking a mutable pointer
+        let ctx = unsafe {
+            ffi::whisper_init_from_buffer_with_params_no_state(bytes.as_ptr() as *mut c_void, bytes.len(), params)
+        };
+        Ok(Arc::new(Self::from_ctx(ctx, source.to_string(), Some(bytes.len() as u64), options, mel_filters)?))
+    }
+
+    /// Load a model streamed from `reader`, which must be positioned at the start of the model
+    pub fn load_from_reader(reader: impl Read, options: &ContextOptions) -> Result<Arc<Model>, WhisperError> {
+        Self::load_from_stream(reader, "<reader>".to_string(), options)
+    }
+
+    /// Load a model stored at `offset` in an open file, e.g. an Android asset
+    /// (`AssetFileDescriptor`). The descriptor stays owned by the caller and its
+    /// file position is left unchanged.
+    #[cfg(unix)]
+    pub fn load_from_fd(fd: std::os::unix::io::RawFd, offset: u64, options: &ContextOptions) -> Result<Arc<Model>, WhisperError> {
+        use std::os::unix::io::BorrowedFd;
+
+        if fd < 0 {
+            return Err(WhisperError::ModelInitError(format!("Invalid file descriptor {}", fd)));
+        }
+
+        // Read through a duplicate at explicit offsets, so the caller's descriptor is untouched
+        let file = unsafe { BorrowedFd::borrow_raw(fd) }
+            .try_clone_to_owned()
+            .map_err(|e| WhisperError::ModelInitError(format!("Invalid file descriptor {}: {}", fd, e)))?;
+        let reader = std::io::BufReader::new(PositionedFile {
+            file: std::fs::File::from(file),
+            position: offset,
+        });
+
+        Self::load_from_stream(reader, format!("<fd {}>", fd), options)
+    }
+
+    fn load_from_stream(mut reader: impl Read, source: String, options: &ContextOptions) -> Result<Arc<Model>, WhisperError> {
+        // Peek at the header to pick the DTW preset and at the mel filters that follow
+        // it, then replay both in front of the rest
+        let mut header = [0u8; HEADER_SIZE];
+        let header_len = read_up_to(&mut reader, &mut header)
+            .map_err(|e| WhisperError::ModelInitError(format!("Failed to read model from {}: {}", source, e)))?;
+        let header = &header[..header_len];
+        let mel_filters = MelFilters::read(&mut reader)?;
+        let mel_filter_bytes = mel_filters.to_bytes();
+
+        let params = context_params(options, &source, || AlignmentHeadsPreset::detect_from_header(header));
+
+        let mut loader = ReaderLoader::new(header.chain(mel_filter_bytes.as_slice()).chain(reader));
+        let mut ffi_loader = loader.as_ffi();
+        let ctx = unsafe { ffi::whisper_init_with_params_no_state(&mut ffi_loader, params) };
+        Ok(Arc::new(Self::from_ctx(ctx, source, None, options, mel_filters)?))
+    }
+
+    fn from_ctx(
+        ctx: *mut c_void,
+        model_path: String,
+        file_size: Option<u64>,
+        options: &ContextOptions,
+        mel_filters: MelFilters,
+    ) -> Result<Model, WhisperError> {
+        if ctx.is_null() {
+            return Err(WhisperError::ModelInitError(format!("Failed to load model from {}", model_path)));
+        }
+
+        Ok(Model {
+            ctx,
+            model_path,
+            file_size,
+            options: options.clone(),
+            mel_filters,
+            idle_states: Mutex::new(Vec::new()),
+            max_idle_states: AtomicUsize::new(DEFAULT_MAX_IDLE_STATES),
+        })
+    }
+
+    /// Mel filter bank stored in the model file
+    pub fn mel_filters(&self) -> &MelFilters {
+        &self.mel_filters
+    }
+
+    /// Path the model was loaded from; `<memory>`, `<reader>` or `<fd N>` for
+    /// models loaded by the other constructors
+    pub fn model_path(&self) -> &str {
+        &self.model_path
+    }
+
+    pub fn options(&self) -> &ContextOptions {
+        &self.options
+    }
+
+    pub(crate) fn ctx(&self) -> *mut c_void {
+        self.ctx
+    }
+
+    /// Architecture, quantization and languages of the loaded model
+    pub fn info(&self) -> ModelInfo {
+        let ctx = self.ctx;
+        let multilingual = unsafe { ffi::whisper_is_multilingual(ctx) } != 0;
+        let ftype = unsafe { ffi::whisper_model_ftype(ctx) };
+
+        let type_ptr = unsafe { ffi::whisper_model_type_readable(ctx) };
+        let model_type = if type_ptr.is_null() {
+            "unknown".to_string()
+        } else {
+            unsafe { CStr::from_ptr(type_ptr) }.to_string_lossy().to_string()
+        };
+
+        let languages = if multilingual {
+            let max_id = unsafe { ffi::whisper_lang_max_id() };
+            (0..=max_id).filter_map(language_code).collect()
+        } else {
+            vec!["en".to_string()]
+        };
+
+        unsafe {
+            ModelInfo {
+                model_type,
+                multilingual,
+                n_vocab: ffi::whisper_model_n_vocab(ctx),
+                n_audio_ctx: ffi::whisper_model_n_audio_ctx(ctx),
+                n_audio_state: ffi::whisper_model_n_audio_state(ctx),
+                n_audio_head: ffi::whisper_model_n_audio_head(ctx),
+                n_audio_layer: ffi::whisper_model_n_audio_layer(ctx),
+                n_text_ctx: ffi::whisper_model_n_text_ctx(ctx),
+                n_text_state: ffi::whisper_model_n_text_state(ctx),
+                n_text_head: ffi::whisper_model_n_text_head(ctx),
+                n_text_layer: ffi::whisper_model_n_text_layer(ctx),
+                n_mels: ffi::whisper_model_n_mels(ctx),
+                ftype,
+                quantization: quantization_name(ftype).to_string(),
+                file_size: self.file_size,
+                languages,
+                model_path: self.model_path.clone(),
+            }
+        }
+    }
+
+    /// Decoding states currently waiting in the pool
+    pub fn idle_states(&self) -> usize {
+        self.idle_states.lock().len()
+    }
+
+    pub fn max_idle_states(&self) -> usize {
+        self.max_idle_states.load(Ordering::Relaxed)
+    }
+
+    /// Keep at most `max` idle decoding states; idle states above it are freed now,
+    /// and states released while the pool is full are freed instead of pooled.
+    /// 0 frees every state as soon as its call finishes.
+    pub fn set_max_idle_states(&self, max: usize) {
+        self.max_idle_states.store(max, Ordering::Relaxed);
+        let surplus = surplus_states(&mut self.idle_states.lock(), max);
+        free_states(surplus);
+    }
+
+    /// Take a decoding state from the pool, creating one if none is idle.
+    /// The state goes back to the pool when the guard is dropped.
+    pub(crate) fn acquire_state(self: &Arc<Self>) -> Result<PooledState, WhisperError> {
+        let idle = self.idle_states.lock().pop();
+        let state = match idle {
+            Some(StatePtr(state)) => state,
+            None => {
+                let state = unsafe { ffi::whisper_init_state(self.ctx) };
+                if state.is_null() {
+                    return Err(WhisperError::ModelInitError("Failed to initialize state".to_string()));
+                }
+                state
+            }
+        };
+
+        Ok(PooledState {
+            model: Arc::clone(self),
+            state,
+        })
+    }
+}
+
+impl Drop for Model {
+    fn drop(&mut self) {
+        // Every busy state holds an Arc to the model, so all states are idle here
+        for StatePtr(state) in self.idle_states.get_mut().drain(..) {
+            unsafe { ffi::whisper_free_state(state) };
+        }
+        unsafe { ffi::whisper_free(self.ctx) };
+    }
+}
+
+/// What a loaded model is, as reported by whisper.cpp
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// "tiny", "base", "small", "medium" or "large"
+    pub model_type: String,
+    pub multilingual: bool,
+    pub n_vocab: i32,
+    pub n_audio_ctx: i32,
+    pub n_audio_state: i32,
+    pub n_audio_head: i32,
+    pub n_audio_layer: i32,
+    pub n_text_ctx: i32,
+    pub n_text_state: i32,
+    pub n_text_head: i32,
+    pub n_text_layer: i32,
+    pub n_mels: i32,
+    /// Weight type from the model header (`enum ggml_ftype`)
+    pub ftype: i32,
+    /// Readable `ftype`, e.g. "f16" or "q5_1"
+    pub quantization: String,
+    /// Size of the model in bytes; `None` when it was streamed from a reader or file descriptor
+    pub file_size: Option<u64>,
+    /// Language codes the model can transcribe
+    pub languages: Vec<String>,
+    /// See `Model::model_path`
+    pub model_path: String,
+}
+
+impl fmt::Display for ModelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, {}, {} audio / {} text layers, {} mels)",
+            self.model_type,
+            if self.multilingual { "multilingual" } else { "English-only" },
+            self.quantization,
+            self.n_audio_layer,
+            self.n_text_layer,
+            self.n_mels,
+        )
+    }
+}
+
+/// Name of a `ggml_ftype` as stored in whisper model headers
+fn quantization_name(ftype: i32) -> &'static str {
+    match ftype {
+        0 => "f32",
+        1 => "f16",
+        2 => "q4_0",
+        3 => "q4_1",
+        4 => "q4_1_some_f16",
+        7 => "q8_0",
+        8 => "q5_0",
+        9 => "q5_1",
+        10 => "q2_k",
+        11 => "q3_k",
+        12 => "q4_k",
+        13 => "q5_k",
+        14 => "q6_k",
+        _ => "unknown",
+    }
+}
+
+/// `whisper_context_params` for `options`; `detect` reads the DTW preset from the model header
+fn context_params(
+    options: &ContextOptions,
+    source: &str,
+    detect: impl FnOnce() -> Option<AlignmentHeadsPreset>,
+) -> ffi::WhisperContextParams {
+    let mut params = unsafe { ffi::whisper_context_default_params() };
+    params.use_gpu = options.use_gpu;
+    params.flash_attn = options.flash_attn;
+    params.gpu_device = options.gpu_device;
+
+    if options.dtw_token_timestamps {
+        match options.dtw_aheads_preset.or_else(detect) {
+            Some(preset) => {
+                params.dtw_token_timestamps = true;
+                params.dtw_aheads_preset = preset as c_int;
+            }
+            None => warn!("Could not determine DTW alignment heads for {}; word timestamps fall back to token timestamps", source),
+        }
+    }
+
+    params
+}
+
+/// Fill as much of `buffer` as the reader has; returns the bytes read
+/// Mel filters of the model file at `path`, which follow the header
+fn read_mel_filters(path: &str) -> Result<MelFilters, WhisperError> {
+    let open_error = |e: std::io::Error| WhisperError::ModelInitError(format!("Cannot read model file {}: {}", path, e));
+    let mut file = std::fs::File::open(path).map_err(open_error)?;
+    file.seek(SeekFrom::Start(HEADER_SIZE as u64)).map_err(open_error)?;
+    MelFilters::read(BufReader::new(file))
+}
+
+fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Reads a file from a given offset without moving the descriptor's shared position
+#[cfg(unix)]
+struct PositionedFile {
+    file: std::fs::File,
+    position: u64,
+}
+
+#[cfg(unix)]
+impl Read for PositionedFile {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+
+        let n = self.file.read_at(buffer, self.position)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+/// A decoding state borrowed from a model's pool
+pub(crate) struct PooledState {
+    model: Arc<Model>,
+    state: *mut c_void,
+}
+
+impl PooledState {
+    pub(crate) fn ctx(&self) -> *mut c_void {
+        self.model.ctx
+    }
+
+    pub(crate) fn ptr(&self) -> *mut c_void {
+        self.state
+    }
+}
+
+impl Drop for PooledState {
+    fn drop(&mut self) {
+        // Free outside the lock; freeing a state can take a while
+        let surplus = {
+            let mut idle = self.model.idle_states.lock();
+            idle.push(StatePtr(self.state));
+            surplus_states(&mut idle, self.model.max_idle_states())
+        };
+        free_states(surplus);
+    }
+}
+
+/// Take the states above `max` out of the pool
+fn surplus_states(idle: &mut Vec<StatePtr>, max: usize) -> Vec<StatePtr> {
+    idle.split_off(max.min(idle.len()))
+}
+
+fn free_states(states: Vec<StatePtr>) {
+    for StatePtr(state) in states {
+        unsafe { ffi::whisper_free_state(state) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_surplus_states_above_the_cap() {
+        let fake = |n: usize| StatePtr(n as *mut c_void);
+        let mut idle: Vec<StatePtr> = (1..=4).map(fake).collect();
+
+        let surplus = surplus_states(&mut idle, 2);
+        assert_eq!(idle.iter().map(|s| s.0 as usize).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(surplus.iter().map(|s| s.0 as usize).collect::<Vec<_>>(), [3, 4]);
+
+        assert!(surplus_states(&mut idle, 5).is_empty());
+        assert_eq!(surplus_states(&mut idle, 0).len(), 2);
+        assert!(idle.is_empty());
+    }
+
+    #[test]
+    fn test_model_info_json_and_display() {
+        let info = ModelInfo {
+            model_type: "base".to_string(),
+            multilingual: true,
+            n_vocab: 51865,
+            n_audio_ctx: 1500,
+            n_audio_state: 512,
+            n_audio_head: 8,
+            n_audio_layer: 6,
+            n_text_ctx: 448,
+            n_text_state: 512,
+            n_text_head: 8,
+            n_text_layer: 6,
+            n_mels: 80,
+            ftype: 9,
+            quantization: quantization_name(9).to_string(),
+            file_size: Some(59_707_625),
+            languages: vec!["en".to_string(), "ar".to_string()],
+            model_path: "ggml-base-q5_1.bin".to_string(),
+        };
+
+        let json: serde_json::Value = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["quantization"], "q5_1");
+        assert_eq!(json["n_audio_layer"], 6);
+        assert_eq!(json["languages"][1], "ar");
+        assert_eq!(info.to_string(), "base (multilingual, q5_1, 6 audio / 6 text layers, 80 mels)");
+        assert_eq!(quantization_name(42), "unknown");
+    }
+}
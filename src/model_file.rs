@@ -0,0 +1,412 @@
+//! Offline inspection of ggml whisper model files
+//!
+//! Walks the file layout (magic, hyperparameters, mel filters, vocabulary and
+//! tensor headers) without reading the weights or touching whisper.cpp, so a
+//! truncated download or a file in the wrong format is caught in milliseconds
+//! instead of failing (or crashing) inside the loader.
+
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::WhisperError;
+
+const GGML_MAGIC: u32 = 0x67676d6c;
+const GGUF_MAGIC: u32 = 0x46554747;
+
+/// Bytes covering the magic and the hyperparameters
+pub(crate) const HEADER_SIZE: usize = 48;
+
+// whisper.cpp stores the quantization version in ftype as version * 1000 + ftype
+const QNT_VERSION_FACTOR: i32 = 1000;
+
+// Sanity limits; real models are far below these
+const MAX_MEL_FILTER_VALUES: i64 = 1 << 20;
+const MAX_TOKEN_LEN: u32 = 1 << 16;
+const MAX_TENSOR_NAME_LEN: i32 = 1 << 10;
+
+/// Hyperparameters at the start of a ggml whisper model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hparams {
+    pub n_vocab: i32,
+    pub n_audio_ctx: i32,
+    pub n_audio_state: i32,
+    pub n_audio_head: i32,
+    pub n_audio_layer: i32,
+    pub n_text_ctx: i32,
+    pub n_text_state: i32,
+    pub n_text_head: i32,
+    pub n_text_layer: i32,
+    pub n_mels: i32,
+    /// `enum ggml_ftype`, without the quantization version
+    pub ftype: i32,
+}
+
+impl Hparams {
+    /// Parse the magic and hyperparameters from the first `HEADER_SIZE` bytes of a model
+    pub fn parse(header: &[u8]) -> Result<Self, WhisperError> {
+        let magic = header
+            .get(..4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| truncated("magic"))?;
+        check_magic(magic)?;
+
+        let values = header.get(4..HEADER_SIZE).ok_or_else(|| truncated("hyperparameters"))?;
+        let mut hparams = [0i32; 11];
+        for (value, bytes) in hparams.iter_mut().zip(values.chunks_exact(4)) {
+            *value = i32::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        let [n_vocab, n_audio_ctx, n_audio_state, n_audio_head, n_audio_layer, n_text_ctx, n_text_state, n_text_head, n_text_layer, n_mels, ftype] = hparams;
+        let hparams = Self {
+            n_vocab,
+            n_audio_ctx,
+            n_audio_state,
+            n_audio_head,
+            n_audio_layer,
+            n_text_ctx,
+            n_text_state,
+            n_text_head,
+            n_text_layer,
+            n_mels,
+            ftype: ftype % QNT_VERSION_FACTOR,
+        };
+        hparams.validate()?;
+        Ok(hparams)
+    }
+
+    fn validate(&self) -> Result<(), WhisperError> {
+        let dimensions = [
+            self.n_vocab,
+            self.n_audio_ctx,
+            self.n_audio_state,
+            self.n_audio_head,
+            self.n_audio_layer,
+            self.n_text_ctx,
+            self.n_text_state,
+            self.n_text_head,
+            self.n_text_layer,
+        ];
+        if dimensions.iter().any(|&value| value <= 0) {
+            return Err(WhisperError::InvalidModel(format!("Invalid hyperparameters {:?}", self)));
+        }
+        if self.n_mels != 80 && self.n_mels != 128 {
+            return Err(WhisperError::InvalidModel(format!("Unsupported number of mel bins: {}", self.n_mels)));
+        }
+        if !matches!(self.ftype, 0..=4 | 7..=14) {
+            return Err(WhisperError::InvalidModel(format!("Unknown weight type (ftype {})", self.ftype)));
+        }
+        Ok(())
+    }
+
+    /// "tiny", "base", "small", "medium" or "large", chosen from the encoder depth like whisper.cpp does
+    pub fn model_type(&self) -> &'static str {
+        match self.n_audio_layer {
+            4 => "tiny",
+            6 => "base",
+            12 => "small",
+            24 => "medium",
+            32 => "large",
+            _ => "unknown",
+        }
+    }
+
+    pub fn is_multilingual(&self) -> bool {
+        self.n_vocab >= 51865
+    }
+}
+
+/// Layout of a model file, checked without loading it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelFile {
+    pub hparams: Hparams,
+    /// Mel filterbank stored in the file
+    pub n_mel_filters: i32,
+    pub n_fft: i32,
+    /// Tokens in the stored vocabulary
+    pub n_tokens: i32,
+    pub n_tensors: usize,
+    pub file_size: u64,
+}
+
+impl ModelFile {
+    /// Check that the file at `path` is a complete ggml whisper model
+    pub fn inspect(path: impl AsRef<Path>) -> Result<Self, WhisperError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| {
+            WhisperError::ModelInitError(format!("Cannot open model file {}: {}", path.display(), e))
+        })?;
+        Self::inspect_reader(file)
+    }
+
+    /// Check a model held in memory
+    pub fn inspect_bytes(bytes: &[u8]) -> Result<Self, WhisperError> {
+        Self::inspect_reader(Cursor::new(bytes))
+    }
+
+    /// Check a model read from the current position of `reader` to its end
+    pub fn inspect_reader<R: Read + Seek>(reader: R) -> Result<Self, WhisperError> {
+        let mut reader = LayoutReader::new(reader)?;
+
+        let mut header = [0u8; HEADER_SIZE];
+        let header_len = reader.read_up_to(&mut header)?;
+        let hparams = Hparams::parse(&header[..header_len])?;
+
+        // Mel filterbank: n_mel x n_fft f32 values
+        let n_mel_filters = reader.read_i32("mel filters")?;
+        let n_fft = reader.read_i32("mel filters")?;
+        let n_values = n_mel_filters as i64 * n_fft as i64;
+        if n_mel_filters <= 0 || n_fft <= 0 || n_values > MAX_MEL_FILTER_VALUES {
+            return Err(WhisperError::InvalidModel(format!("Invalid mel filterbank size {} x {}", n_mel_filters, n_fft)));
+        }
+        reader.skip(n_values as u64 * 4, "mel filters")?;
+
+        // Vocabulary: count, then length-prefixed tokens. Files store only the base
+        // vocabulary (50257 tokens for a multilingual n_vocab of 51865); whisper.cpp
+        // adds the special tokens after it
+        let n_tokens = reader.read_i32("vocabulary")?;
+        if n_tokens <= 0 || n_tokens > hparams.n_vocab {
+            return Err(WhisperError::InvalidModel(format!(
+                "Vocabulary has {} tokens, header allows at most {}",
+                n_tokens, hparams.n_vocab
+            )));
+        }
+        for _ in 0..n_tokens {
+            let len = reader.read_i32("vocabulary")? as u32;
+            if len > MAX_TOKEN_LEN {
+                return Err(WhisperError::InvalidModel(format!("Invalid vocabulary token length {}", len)));
+            }
+            reader.skip(len as u64, "vocabulary")?;
+        }
+
+        // Tensors until the end of the file
+        let mut n_tensors = 0;
+        while reader.remaining() > 0 {
+            reader.skip_tensor()?;
+            n_tensors += 1;
+        }
+        if n_tensors == 0 {
+            return Err(truncated("tensor data"));
+        }
+
+        Ok(Self {
+            hparams,
+            n_mel_filters,
+            n_fft,
+            n_tokens,
+            n_tensors,
+            file_size: reader.len,
+        })
+    }
+}
+
+fn check_magic(magic: u32) -> Result<(), WhisperError> {
+    match magic {
+        GGML_MAGIC => Ok(()),
+        GGUF_MAGIC => Err(WhisperError::UnsupportedModelFormat(
+            "GGUF files can't be loaded by whisper.cpp; use a ggml .bin whisper model".to_string(),
+        )),
+        _ => Err(WhisperError::UnsupportedModelFormat(format!("Not a ggml whisper model (magic 0x{:08x})", magic))),
+    }
+}
+
+fn truncated(section: &str) -> WhisperError {
+    WhisperError::TruncatedModel(format!("file ends inside the {}", section))
+}
+
+/// Bytes per block and elements per block of a `ggml_type`
+pub(crate) fn ggml_type_size(ttype: i32) -> Option<(u64, u64)> {
+    let size = match ttype {
+        0 => (4, 1),     // F32
+        1 => (2, 1),     // F16
+        2 => (18, 32),   // Q4_0
+        3 => (20, 32),   // Q4_1
+        6 => (22, 32),   // Q5_0
+        7 => (24, 32),   // Q5_1
+        8 => (34, 32),   // Q8_0
+        9 => (36, 32),   // Q8_1
+        10 => (84, 256), // Q2_K
+        11 => (110, 256),
+        12 => (144, 256),
+        13 => (176, 256),
+        14 => (210, 256),
+        15 => (292, 256),
+        30 => (2, 1),    // BF16
+        _ => return None,
+    };
+    Some(size)
+}
+
+/// Reads a model front to back, tracking the position against the known length.
+/// Tensor data is skipped with `seek_relative`, which keeps the buffer when the
+/// next header is already in it.
+struct LayoutReader<R> {
+    reader: BufReader<R>,
+    position: u64,
+    len: u64,
+}
+
+impl<R: Read + Seek> LayoutReader<R> {
+    fn new(mut reader: R) -> Result<Self, WhisperError> {
+        let io_error = |e: std::io::Error| WhisperError::ModelInitError(format!("Failed to read model: {}", e));
+        let start = reader.stream_position().map_err(io_error)?;
+        let end = reader.seek(SeekFrom::End(0)).map_err(io_error)?;
+        reader.seek(SeekFrom::Start(start)).map_err(io_error)?;
+
+        Ok(Self {
+            reader: BufReader::new(reader),
+            position: 0,
+            len: end.saturating_sub(start),
+        })
+    }
+
+    fn remaining(&self) -> u64 {
+        self.len - self.position
+    }
+
+    fn read_up_to(&mut self, buffer: &mut [u8]) -> Result<usize, WhisperError> {
+        let len = buffer.len().min(self.remaining() as usize);
+        self.read_exact(&mut buffer[..len], "header")?;
+        Ok(len)
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8], section: &str) -> Result<(), WhisperError> {
+        if (buffer.len() as u64) > self.remaining() {
+            return Err(truncated(section));
+        }
+        self.reader
+            .read_exact(buffer)
+            .map_err(|e| WhisperError::ModelInitError(format!("Failed to read model {}: {}", section, e)))?;
+        self.position += buffer.len() as u64;
+        Ok(())
+    }
+
+    fn read_i32(&mut self, section: &str) -> Result<i32, WhisperError> {
+        let mut bytes = [0u8; 4];
+        self.read_exact(&mut bytes, section)?;
+        Ok(i32::from_le_bytes(bytes))
+    }
+
+    fn skip(&mut self, n: u64, section: &str) -> Result<(), WhisperError> {
+        if n > self.remaining() {
+            return Err(truncated(section));
+        }
+        self.reader
+            .seek_relative(n as i64)
+            .map_err(|e| WhisperError::ModelInitError(format!("Failed to read model {}: {}", section, e)))?;
+        self.position += n;
+        Ok(())
+    }
+
+    /// Skip one tensor: n_dims, name length, type, dimensions, name, data
+    fn skip_tensor(&mut self) -> Result<(), WhisperError> {
+        let n_dims = self.read_i32("tensor header")?;
+        let name_len = self.read_i32("tensor header")?;
+        let ttype = self.read_i32("tensor header")?;
+        if !(1..=4).contains(&n_dims) || !(1..=MAX_TENSOR_NAME_LEN).contains(&name_len) {
+            return Err(WhisperError::InvalidModel(format!(
+                "Invalid tensor header (n_dims {}, name length {})",
+                n_dims, name_len
+            )));
+        }
+
+        let mut n_elements: u64 = 1;
+        let mut row_len: u64 = 0;
+        for dim in 0..n_dims {
+            let ne = self.read_i32("tensor header")?;
+            if ne <= 0 {
+                return Err(WhisperError::InvalidModel(format!("Invalid tensor dimension {}", ne)));
+            }
+            if dim == 0 {
+                row_len = ne as u64;
+            }
+            n_elements = n_elements.saturating_mul(ne as u64);
+        }
+
+        let mut name = vec![0u8; name_len as usize];
+        self.read_exact(&mut name, "tensor header")?;
+        let name = String::from_utf8_lossy(&name);
+
+        let (block_size, block_len) = ggml_type_size(ttype)
+            .ok_or_else(|| WhisperError::InvalidModel(format!("Tensor '{}' has unknown type {}", name, ttype)))?;
+        if !row_len.is_multiple_of(block_len) {
+            return Err(WhisperError::InvalidModel(format!(
+                "Tensor '{}' row of {} values doesn't fit blocks of {}",
+                name, row_len, block_len
+            )));
+        }
+
+        let data_size = (n_elements / block_len).saturating_mul(block_size);
+        self.skip(data_size, &format!("data of tensor '{}'", name))
+    }
+}
+
+/// Smallest well-formed model: tiny hparams with n_vocab 4, 2 stored tokens (like
+/// real files, which leave out the special tokens) and one F32 tensor
+#[cfg(test)]
+pub(crate) fn test_model() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut push = |value: i32| bytes.extend_from_slice(&value.to_le_bytes());
+    push(GGML_MAGIC as i32);
+    for value in [4, 1500, 384, 6, 4, 448, 384, 6, 4, 80, 1] {
+        push(value);
+    }
+    // Mel filters 80 x 1
+    push(80);
+    push(1);
+    for _ in 0..80 {
+        push(0);
+    }
+    // Vocabulary
+    push(2);
+    push(1);
+    bytes.push(b'a');
+    bytes.extend_from_slice(&1i32.to_le_bytes());
+    bytes.push(b'b');
+    // Tensor: 1 dim of 3 f32 values
+    for value in [1i32, 4, 0, 3] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(b"ln.w");
+    bytes.extend_from_slice(&[0u8; 12]);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect_complete_model() {
+        let model = ModelFile::inspect_bytes(&test_model()).unwrap();
+        assert_eq!(model.hparams.n_audio_layer, 4);
+        assert_eq!(model.hparams.model_type(), "tiny");
+        assert_eq!(model.hparams.ftype, 1);
+        assert_eq!((model.n_mel_filters, model.n_fft, model.n_tokens, model.n_tensors), (80, 1, 2, 1));
+        assert_eq!(model.file_size, test_model().len() as u64);
+    }
+
+    #[test]
+    fn test_truncated_and_foreign_files() {
+        let model = test_model();
+        for len in [0, 3, 20, 100, model.len() - 1] {
+            let err = ModelFile::inspect_bytes(&model[..len]).unwrap_err();
+            assert!(matches!(err, WhisperError::TruncatedModel(_)), "len {}: {:?}", len, err);
+        }
+
+        let mut gguf = model.clone();
+        gguf[..4].copy_from_slice(b"GGUF");
+        assert!(matches!(ModelFile::inspect_bytes(&gguf), Err(WhisperError::UnsupportedModelFormat(_))));
+        assert!(matches!(ModelFile::inspect_bytes(b"<html>404</html>"), Err(WhisperError::UnsupportedModelFormat(_))));
+
+        let mut bad_mels = model.clone();
+        bad_mels[40..44].copy_from_slice(&7i32.to_le_bytes());
+        assert!(matches!(ModelFile::inspect_bytes(&bad_mels), Err(WhisperError::InvalidModel(_))));
+
+        // More stored tokens than the header's n_vocab
+        let vocab_offset = 4 + 11 * 4 + 8 + 80 * 4;
+        let mut bad_vocab = model;
+        bad_vocab[vocab_offset..vocab_offset + 4].copy_from_slice(&5i32.to_le_bytes());
+        assert!(matches!(ModelFile::inspect_bytes(&bad_vocab), Err(WhisperError::InvalidModel(_))));
+    }
+}
@@ -0,0 +1,270 @@
+//! Named models in a local directory, verified before they are loaded
+//!
+//! A models directory holds ggml files and an optional `models.json` manifest:
+//!
+//! ```json
+//! { "models": [
+//!     { "name": "tiny-ar", "file": "ggml-tiny.bin", "size": "tiny",
+//!       "languages": ["ar"], "sha256": "be07e048e1e599ad46341c8d2a135645097a538221678b7acdd1b1919c6e1b21" }
+//! ] }
+//! ```
+//!
+//! `.bin` files missing from the manifest are still listed, named after the file
+//! (`ggml-base.en.bin` is "base.en"), but can only be checked structurally.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::{ContextOptions, ModelFile, Whisper, WhisperError};
+
+/// Manifest file name inside a models directory
+pub const MANIFEST_FILE: &str = "models.json";
+
+// Files that passed verification, keyed by path; re-hashed only if they change
+static VERIFIED: Lazy<Mutex<HashMap<PathBuf, FileStamp>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    sha256: Option<String>,
+}
+
+impl FileStamp {
+    fn read(path: &Path, sha256: Option<&str>) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            sha256: sha256.map(str::to_ascii_lowercase),
+        })
+    }
+}
+
+/// A model listed in the manifest or found in the directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelEntry {
+    /// Name used to request the model, e.g. "tiny-ar" or "base"
+    pub name: String,
+    /// File name relative to the models directory; absolute paths and `..` are rejected
+    pub file: String,
+    /// "tiny", "base", "small", ...; `None` for files not in the manifest
+    #[serde(default)]
+    pub size: Option<String>,
+    /// Languages the model is meant for; empty if unspecified
+    #[serde(default)]
+    pub languages: Vec<String>,
+    /// Expected SHA-256 of the file as hex; files without one are only checked structurally
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    models: Vec<ModelEntry>,
+}
+
+/// Models available in a local directory
+#[derive(Debug, Clone)]
+pub struct ModelRegistry {
+    dir: PathBuf,
+    entries: Vec<ModelEntry>,
+}
+
+impl ModelRegistry {
+    /// Read the manifest in `dir` (if any) and list the model files next to it
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, WhisperError> {
+        let dir = dir.as_ref().to_path_buf();
+        let manifest_path = dir.join(MANIFEST_FILE);
+
+        let mut entries = if manifest_path.exists() {
+            let manifest = fs::read_to_string(&manifest_path).map_err(|e| {
+                WhisperError::InvalidParameter(format!("Cannot read {}: {}", manifest_path.display(), e))
+            })?;
+            let manifest: Manifest = serde_json::from_str(&manifest).map_err(|e| {
+                WhisperError::InvalidParameter(format!("Invalid manifest {}: {}", manifest_path.display(), e))
+            })?;
+            manifest.models
+        } else {
+            Vec::new()
+        };
+
+        let listed: Vec<String> = entries.iter().map(|entry| entry.file.clone()).collect();
+        let files = fs::read_dir(&dir)
+            .map_err(|e| WhisperError::InvalidParameter(format!("Cannot read models directory {}: {}", dir.display(), e)))?;
+
+        let mut unlisted: Vec<ModelEntry> = files
+            .filter_map(|file| file.ok()?.file_name().into_string().ok())
+            .filter(|file| file.ends_with(".bin") && !listed.contains(file))
+            .map(|file| ModelEntry {
+                name: name_from_file(&file),
+                file,
+                size: None,
+                languages: Vec::new(),
+                sha256: None,
+            })
+            .collect();
+        unlisted.sort_by(|a, b| a.name.cmp(&b.name));
+        entries.extend(unlisted);
+
+        Ok(Self { dir, entries })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn entries(&self) -> &[ModelEntry] {
+        &self.entries
+    }
+
+    /// Look a model up by name; the first entry with that name wins
+    pub fn get(&self, name: &str) -> Option<&ModelEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Path of a named model, without checking the file
+    pub fn path(&self, name: &str) -> Result<PathBuf, WhisperError> {
+        self.file_path(self.entry(name)?)
+    }
+
+    /// Check a named model is complete and matches its checksum, and return its path.
+    /// A file that passed before is not hashed again unless its size or mtime changed.
+    pub fn verify(&self, name: &str) -> Result<PathBuf, WhisperError> {
+        let entry = self.entry(name)?;
+        let path = self.file_path(entry)?;
+
+        let stamp = FileStamp::read(&path, entry.sha256.as_deref())
+            .ok_or_else(|| WhisperError::ModelInitError(format!("Model file not found: {}", path.display())))?;
+        if VERIFIED.lock().get(&path) == Some(&stamp) {
+            return Ok(path);
+        }
+
+        ModelFile::inspect(&path)?;
+
+        if let Some(expected) = &stamp.sha256 {
+            let actual = sha256_file(&path)?;
+            if &actual != expected {
+                return Err(WhisperError::ChecksumMismatch(format!(
+                    "{} has SHA-256 {}, expected {}",
+                    path.display(),
+                    actual,
+                    expected
+                )));
+            }
+        }
+
+        VERIFIED.lock().insert(path.clone(), stamp);
+        Ok(path)
+    }
+
+    /// Verify a named model and load it
+    pub fn load(&self, name: &str, options: ContextOptions) -> Result<Whisper, WhisperError> {
+        Whisper::load(self.verify(name)?, options)
+    }
+
+    /// Path of an entry's file, which must stay inside the models directory
+    fn file_path(&self, entry: &ModelEntry) -> Result<PathBuf, WhisperError> {
+        let file = Path::new(&entry.file);
+        let inside = file
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !inside || file.file_name().is_none() {
+            return Err(WhisperError::InvalidParameter(format!(
+                "Model '{}' has file '{}' outside {}",
+                entry.name,
+                entry.file,
+                self.dir.display()
+            )));
+        }
+        Ok(self.dir.join(file))
+    }
+
+    fn entry(&self, name: &str) -> Result<&ModelEntry, WhisperError> {
+        self.get(name).ok_or_else(|| {
+            WhisperError::InvalidParameter(format!("Unknown model '{}' in {}", name, self.dir.display()))
+        })
+    }
+}
+
+/// "ggml-base.en.bin" -> "base.en"
+fn name_from_file(file: &str) -> String {
+    let stem = file.strip_suffix(".bin").unwrap_or(file);
+    stem.strip_prefix("ggml-").unwrap_or(stem).to_string()
+}
+
+/// Lowercase hex SHA-256 of a file
+pub fn sha256_file(path: impl AsRef<Path>) -> Result<String, WhisperError> {
+    let path = path.as_ref();
+    let read_error = |e: std::io::Error| WhisperError::ModelInitError(format!("Cannot read {}: {}", path.display(), e));
+
+    let mut file = File::open(path).map_err(read_error)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buffer).map_err(read_error)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_file::test_model;
+
+    fn models_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("whisper-registry-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_lookup_and_verify() {
+        let dir = models_dir("verify");
+        let model = test_model();
+        fs::write(dir.join("ggml-tiny.bin"), &model).unwrap();
+        fs::write(dir.join("ggml-base.en.bin"), &model).unwrap();
+        fs::write(dir.join("ggml-small.bin"), &model).unwrap();
+
+        let sha256: String = Sha256::digest(&model).iter().map(|byte| format!("{:02x}", byte)).collect();
+        let manifest = serde_json::json!({ "models": [
+            { "name": "tiny-ar", "file": "ggml-tiny.bin", "size": "tiny", "languages": ["ar"], "sha256": sha256.to_uppercase() },
+            { "name": "small", "file": "ggml-small.bin", "sha256": "00".repeat(32) },
+            { "name": "parent", "file": "../ggml-tiny.bin" },
+            { "name": "absolute", "file": dir.join("ggml-tiny.bin") },
+        ] });
+        fs::write(dir.join(MANIFEST_FILE), manifest.to_string()).unwrap();
+
+        let registry = ModelRegistry::open(&dir).unwrap();
+        let names: Vec<&str> = registry.entries().iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["tiny-ar", "small", "parent", "absolute", "base.en"]);
+        assert_eq!(registry.get("tiny-ar").unwrap().languages, ["ar"]);
+
+        assert_eq!(registry.verify("tiny-ar").unwrap(), dir.join("ggml-tiny.bin"));
+        assert!(registry.verify("base.en").is_ok());
+        assert!(matches!(registry.verify("small"), Err(WhisperError::ChecksumMismatch(_))));
+        assert!(matches!(registry.verify("large"), Err(WhisperError::InvalidParameter(_))));
+        for name in ["parent", "absolute"] {
+            assert!(matches!(registry.path(name), Err(WhisperError::InvalidParameter(_))), "{}", name);
+            assert!(matches!(registry.verify(name), Err(WhisperError::InvalidParameter(_))), "{}", name);
+        }
+
+        // A partial download is caught even after an earlier successful check
+        fs::write(dir.join("ggml-tiny.bin"), &model[..model.len() / 2]).unwrap();
+        assert!(matches!(registry.verify("tiny-ar"), Err(WhisperError::TruncatedModel(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,815 @@
+//! Options for loading models and running transcriptions
+
+use std::ffi::{c_int, c_void, CString};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::ptr::null_mut;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use crate::callbacks::{self, Callbacks};
+use crate::cancel::{self, AbortReason, AbortSignal};
+use crate::logits::{self, LogitsFilter, LogitsFilterRun, SharedLogitsFilter};
+use crate::model_file::{Hparams, HEADER_SIZE};
+use crate::{ffi, CancellationToken, Grammar, WhisperError};
+
+/// whisper.cpp alignment-head presets used for DTW token timestamps
+/// (values mirror `enum whisper_alignment_heads_preset`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(i32)]
+pub enum AlignmentHeadsPreset {
+    TinyEn = 3,
+    Tiny = 4,
+    BaseEn = 5,
+    Base = 6,
+    SmallEn = 7,
+    Small = 8,
+    MediumEn = 9,
+    Medium = 10,
+    LargeV1 = 11,
+    LargeV2 = 12,
+    LargeV3 = 13,
+    LargeV3Turbo = 14,
+}
+
+impl AlignmentHeadsPreset {
+    /// Pick the preset matching a model's hyperparameters.
+    /// large-v1 and large-v2 share hparams; large-v2 is assumed.
+    pub fn from_hparams(n_vocab: i32, n_audio_layer: i32, n_text_layer: i32, n_mels: i32) -> Option<Self> {
+        let english_only = n_vocab == 51864;
+
+        let preset = match (n_audio_layer, english_only) {
+            (4, true) => Self::TinyEn,
+            (4, false) => Self::Tiny,
+            (6, true) => Self::BaseEn,
+            (6, false) => Self::Base,
+            (12, true) => Self::SmallEn,
+            (12, false) => Self::Small,
+            (24, true) => Self::MediumEn,
+            (24, false) => Self::Medium,
+            (32, false) if n_mels == 128 && n_text_layer == 4 => Self::LargeV3Turbo,
+            (32, false) if n_mels == 128 => Self::LargeV3,
+            (32, false) => Self::LargeV2,
+            _ => return None,
+        };
+
+        Some(preset)
+    }
+
+    /// Read the model header and pick the matching preset
+    pub fn detect(model_path: &str) -> Option<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        File::open(Path::new(model_path)).ok()?.read_exact(&mut header).ok()?;
+        Self::detect_from_header(&header)
+    }
+
+    /// Pick the preset from the first bytes of a model held in memory
+    pub fn detect_from_header(header: &[u8]) -> Option<Self> {
+        let hparams = Hparams::parse(header).ok()?;
+        Self::from_hparams(hparams.n_vocab, hparams.n_audio_layer, hparams.n_text_layer, hparams.n_mels)
+    }
+}
+
+/// Options applied when a model is loaded (`whisper_context_params`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextOptions {
+    pub use_gpu: bool,
+    pub flash_attn: bool,
+    pub gpu_device: i32,
+    /// Compute DTW-aligned token timestamps (`TokenData::t_dtw_ms`), used for word timing
+    pub dtw_token_timestamps: bool,
+    /// Alignment heads for DTW; `None` picks the preset matching the loaded model
+    pub dtw_aheads_preset: Option<AlignmentHeadsPreset>,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            use_gpu: true,
+            flash_attn: false,
+            gpu_device: 0,
+            dtw_token_timestamps: false,
+            dtw_aheads_preset: None,
+        }
+    }
+}
+
+impl ContextOptions {
+    /// Default options with DTW word timestamps enabled
+    pub fn with_word_timestamps() -> Self {
+        Self {
+            dtw_token_timestamps: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Decoding strategy (`whisper_sampling_strategy` plus its parameters)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SamplingStrategy {
+    /// Greedy decoding; `best_of` candidates are sampled when falling back to a higher temperature
+    Greedy { best_of: i32 },
+    /// Beam search; `patience` of -1.0 uses whisper's default
+    BeamSearch { beam_size: i32, patience: f32 },
+}
+
+impl Default for SamplingStrategy {
+    fn default() -> Self {
+        SamplingStrategy::Greedy { best_of: 5 }
+    }
+}
+
+/// Silero voice activity detection run by whisper.cpp before decoding
+/// (`whisper_vad_params` plus the VAD model path). Only detected speech is decoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VadOptions {
+    /// Path to a ggml Silero VAD model (e.g. `ggml-silero-v5.1.2.bin`)
+    pub model_path: String,
+    /// Speech probability above which a frame counts as speech
+    pub threshold: f32,
+    /// Speech shorter than this is discarded
+    pub min_speech_duration_ms: i32,
+    /// Silence shorter than this does not end a speech region
+    pub min_silence_duration_ms: i32,
+    /// Longer speech regions are split
+    pub max_speech_duration_s: f32,
+    /// Padding added on both sides of each speech region
+    pub speech_pad_ms: i32,
+    /// Overlap in seconds when copying audio between speech regions
+    pub samples_overlap: f32,
+}
+
+impl Default for VadOptions {
+    fn default() -> Self {
+        Self {
+            model_path: String::new(),
+            threshold: 0.5,
+            min_speech_duration_ms: 250,
+            min_silence_duration_ms: 2000,
+            max_speech_duration_s: f32::MAX,
+            speech_pad_ms: 30,
+            samples_overlap: 0.1,
+        }
+    }
+}
+
+impl VadOptions {
+    /// whisper.cpp's default VAD parameters with the given model
+    pub fn new(model_path: impl Into<String>) -> Self {
+        Self {
+            model_path: model_path.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn min_speech_duration_ms(mut self, min_speech_duration_ms: i32) -> Self {
+        self.min_speech_duration_ms = min_speech_duration_ms;
+        self
+    }
+
+    pub fn min_silence_duration_ms(mut self, min_silence_duration_ms: i32) -> Self {
+        self.min_silence_duration_ms = min_silence_duration_ms;
+        self
+    }
+
+    pub fn max_speech_duration_s(mut self, max_speech_duration_s: f32) -> Self {
+        self.max_speech_duration_s = max_speech_duration_s;
+        self
+    }
+
+    pub fn speech_pad_ms(mut self, speech_pad_ms: i32) -> Self {
+        self.speech_pad_ms = speech_pad_ms;
+        self
+    }
+
+    pub fn samples_overlap(mut self, samples_overlap: f32) -> Self {
+        self.samples_overlap = samples_overlap;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), WhisperError> {
+        if self.model_path.is_empty() {
+            return Err(WhisperError::InvalidParameter("VAD model path is required".to_string()));
+        }
+
+        if !(0.0..=1.0).contains(&self.threshold) {
+            return Err(WhisperError::InvalidParameter("VAD threshold must be between 0 and 1".to_string()));
+        }
+
+        if self.min_speech_duration_ms < 0
+            || self.min_silence_duration_ms < 0
+            || self.speech_pad_ms < 0
+            || self.max_speech_duration_s <= 0.0
+            || self.samples_overlap < 0.0
+        {
+            return Err(WhisperError::InvalidParameter("VAD durations must not be negative".to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn to_raw(&self) -> ffi::WhisperVadParams {
+        ffi::WhisperVadParams {
+            threshold: self.threshold,
+            min_speech_duration_ms: self.min_speech_duration_ms,
+            min_silence_duration_ms: self.min_silence_duration_ms,
+            max_speech_duration_s: self.max_speech_duration_s,
+            speech_pad_ms: self.speech_pad_ms,
+            samples_overlap: self.samples_overlap,
+        }
+    }
+}
+
+/// Options for a single transcription (`whisper_full_params`).
+///
+/// Defaults follow `whisper_full_default_params`, except `n_threads` which stays at 4.
+/// Fields can be set directly or through the chainable setters:
+///
+/// ```
+/// use whisper_rust_binding::TranscribeOptions;
+///
+/// let options = TranscribeOptions::new()
+///     .language("ar")
+///     .beam_search(5, -1.0)
+///     .no_speech_thold(0.5);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscribeOptions {
+    /// Language code, or `None` for auto-detection
+    pub language: Option<String>,
+    pub translate: bool,
+    pub n_threads: i32,
+    pub strategy: SamplingStrategy,
+
+    /// Initial decoding temperature
+    pub temperature: f32,
+    /// Temperature increase for each fallback; 0.0 disables fallback
+    pub temperature_inc: f32,
+    /// Fall back when the compression-ratio entropy exceeds this
+    pub entropy_thold: f32,
+    /// Fall back when the average log probability is below this
+    pub logprob_thold: f32,
+    /// Treat a segment as silence above this no-speech probability
+    pub no_speech_thold: f32,
+
+    /// Maximum segment length in characters (0 = no limit)
+    pub max_len: i32,
+    /// Split segments on word rather than token boundaries (with `max_len`)
+    pub split_on_word: bool,
+    /// Maximum tokens per segment (0 = no limit)
+    pub max_tokens: i32,
+    /// Audio context size (0 = full context); smaller is faster but less accurate
+    pub audio_ctx: i32,
+
+    pub suppress_blank: bool,
+    /// Suppress non-speech tokens such as music and sound annotations
+    pub suppress_nst: bool,
+    /// Force a single output segment (useful for short streaming windows)
+    pub single_segment: bool,
+    /// Do not use previous transcription as decoder context
+    pub no_context: bool,
+
+    /// Text used to bias decoding (e.g. the expected ayah or domain vocabulary)
+    pub initial_prompt: Option<String>,
+    /// Pre-tokenized prompt; when non-empty it takes precedence over `initial_prompt`
+    pub prompt_tokens: Vec<i32>,
+
+    /// When auto-detecting, only choose among these language codes (e.g. `["ar", "en"]`)
+    pub allowed_languages: Vec<String>,
+
+    /// Constrain decoding to a GBNF grammar (serialized as its GBNF text)
+    pub grammar: Option<Grammar>,
+    /// Name of the rule decoding starts from
+    pub grammar_rule: String,
+    /// Logit penalty for tokens the grammar does not allow
+    pub grammar_penalty: f32,
+
+    /// Skip non-speech audio with whisper.cpp's Silero VAD before decoding
+    pub vad: Option<VadOptions>,
+
+    /// Adjust token scores before every decoding step
+    #[serde(skip)]
+    pub logits_filter: Option<SharedLogitsFilter>,
+
+    /// Token that stops the call early; completed segments are kept
+    #[serde(skip)]
+    pub cancellation: Option<CancellationToken>,
+    /// Abort the call after this many milliseconds; completed segments are kept
+    pub timeout_ms: Option<u64>,
+}
+
+impl Default for TranscribeOptions {
+    fn default() -> Self {
+        Self {
+            language: None,
+            translate: false,
+            n_threads: 4,
+            strategy: SamplingStrategy::default(),
+            temperature: 0.0,
+            temperature_inc: 0.2,
+            entropy_thold: 2.4,
+            logprob_thold: -1.0,
+            no_speech_thold: 0.6,
+            max_len: 0,
+            split_on_word: false,
+            max_tokens: 0,
+            audio_ctx: 0,
+            suppress_blank: true,
+            suppress_nst: false,
+            single_segment: false,
+            no_context: true,
+            initial_prompt: None,
+            prompt_tokens: Vec::new(),
+            allowed_languages: Vec::new(),
+            grammar: None,
+            grammar_rule: "root".to_string(),
+            grammar_penalty: 100.0,
+            vad: None,
+            logits_filter: None,
+            cancellation: None,
+            timeout_ms: None,
+        }
+    }
+}
+
+impl TranscribeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cheap settings for live preview: greedy, no temperature fallback, one segment
+    pub fn fast_preview() -> Self {
+        Self {
+            strategy: SamplingStrategy::Greedy { best_of: 1 },
+            temperature_inc: 0.0,
+            single_segment: true,
+            ..Self::default()
+        }
+    }
+
+    /// Slower settings for reviewing a finished recording: beam search with fallback
+    pub fn accurate_review() -> Self {
+        Self {
+            strategy: SamplingStrategy::BeamSearch { beam_size: 5, patience: -1.0 },
+            ..Self::default()
+        }
+    }
+
+    /// Default options with an optional language, as taken by the simple API
+    pub fn for_language(language: Option<&str>) -> Self {
+        Self {
+            language: language.map(str::to_string),
+            ..Self::default()
+        }
+    }
+
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    pub fn translate(mut self, translate: bool) -> Self {
+        self.translate = translate;
+        self
+    }
+
+    pub fn n_threads(mut self, n_threads: i32) -> Self {
+        self.n_threads = n_threads;
+        self
+    }
+
+    pub fn greedy(mut self, best_of: i32) -> Self {
+        self.strategy = SamplingStrategy::Greedy { best_of };
+        self
+    }
+
+    pub fn beam_search(mut self, beam_size: i32, patience: f32) -> Self {
+        self.strategy = SamplingStrategy::BeamSearch { beam_size, patience };
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn temperature_inc(mut self, temperature_inc: f32) -> Self {
+        self.temperature_inc = temperature_inc;
+        self
+    }
+
+    pub fn entropy_thold(mut self, entropy_thold: f32) -> Self {
+        self.entropy_thold = entropy_thold;
+        self
+    }
+
+    pub fn logprob_thold(mut self, logprob_thold: f32) -> Self {
+        self.logprob_thold = logprob_thold;
+        self
+    }
+
+    pub fn no_speech_thold(mut self, no_speech_thold: f32) -> Self {
+        self.no_speech_thold = no_speech_thold;
+        self
+    }
+
+    pub fn max_len(mut self, max_len: i32) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn split_on_word(mut self, split_on_word: bool) -> Self {
+        self.split_on_word = split_on_word;
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: i32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn audio_ctx(mut self, audio_ctx: i32) -> Self {
+        self.audio_ctx = audio_ctx;
+        self
+    }
+
+    pub fn suppress_blank(mut self, suppress_blank: bool) -> Self {
+        self.suppress_blank = suppress_blank;
+        self
+    }
+
+    pub fn suppress_nst(mut self, suppress_nst: bool) -> Self {
+        self.suppress_nst = suppress_nst;
+        self
+    }
+
+    pub fn single_segment(mut self, single_segment: bool) -> Self {
+        self.single_segment = single_segment;
+        self
+    }
+
+    pub fn no_context(mut self, no_context: bool) -> Self {
+        self.no_context = no_context;
+        self
+    }
+
+    pub fn initial_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.initial_prompt = Some(prompt.into());
+        self
+    }
+
+    pub fn prompt_tokens(mut self, tokens: Vec<i32>) -> Self {
+        self.prompt_tokens = tokens;
+        self
+    }
+
+    pub fn allowed_languages<S: Into<String>>(mut self, languages: impl IntoIterator<Item = S>) -> Self {
+        self.allowed_languages = languages.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Constrain output to `grammar`, e.g. `Grammar::from_phrases(&candidate_ayat)?`
+    pub fn grammar(mut self, grammar: Grammar) -> Self {
+        self.grammar = Some(grammar);
+        self
+    }
+
+    pub fn grammar_rule(mut self, rule: impl Into<String>) -> Self {
+        self.grammar_rule = rule.into();
+        self
+    }
+
+    pub fn grammar_penalty(mut self, grammar_penalty: f32) -> Self {
+        self.grammar_penalty = grammar_penalty;
+        self
+    }
+
+    pub fn vad(mut self, vad: VadOptions) -> Self {
+        self.vad = Some(vad);
+        self
+    }
+
+    pub fn logits_filter(mut self, filter: impl LogitsFilter + 'static) -> Self {
+        self.logits_filter = Some(SharedLogitsFilter::new(filter));
+        self
+    }
+
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Deadline for a call starting now, from `timeout_ms`
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.timeout_ms.map(|ms| Instant::now() + std::time::Duration::from_millis(ms))
+    }
+
+    /// Check values whisper.cpp would otherwise silently misbehave on
+    pub fn validate(&self) -> Result<(), WhisperError> {
+        if self.n_threads < 1 {
+            return Err(WhisperError::InvalidParameter("n_threads must be at least 1".to_string()));
+        }
+
+        match self.strategy {
+            SamplingStrategy::Greedy { best_of } if best_of < 1 => {
+                return Err(WhisperError::InvalidParameter("best_of must be at least 1".to_string()));
+            }
+            SamplingStrategy::BeamSearch { beam_size, .. } if beam_size < 1 => {
+                return Err(WhisperError::InvalidParameter("beam_size must be at least 1".to_string()));
+            }
+            _ => {}
+        }
+
+        if self.temperature < 0.0 || self.temperature_inc < 0.0 {
+            return Err(WhisperError::InvalidParameter("temperature values must not be negative".to_string()));
+        }
+
+        if self.max_len < 0 || self.max_tokens < 0 || self.audio_ctx < 0 {
+            return Err(WhisperError::InvalidParameter("max_len, max_tokens and audio_ctx must not be negative".to_string()));
+        }
+
+        if let Some(vad) = &self.vad {
+            vad.validate()?;
+        }
+
+        if let Some(grammar) = &self.grammar {
+            if grammar.rule_index(&self.grammar_rule).is_none() {
+                return Err(WhisperError::InvalidParameter(format!("Grammar has no rule named '{}'", self.grammar_rule)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `prompt_tokens` must be ids of the model's vocabulary; whisper.cpp indexes its
+    /// tables with them unchecked
+    pub fn validate_prompt_tokens(&self, n_vocab: usize) -> Result<(), WhisperError> {
+        match self.prompt_tokens.iter().find(|&&token| token < 0 || token as usize >= n_vocab) {
+            Some(token) => Err(WhisperError::InvalidParameter(format!(
+                "Prompt token {} is outside the vocabulary of {} tokens",
+                token, n_vocab
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Build the raw whisper.cpp parameters for a model with `n_vocab` tokens. The
+    /// returned value owns every string the raw struct points to, so it must outlive
+    /// the `whisper_full_with_state` call.
+    pub(crate) fn to_full_params(&self, n_vocab: usize) -> Result<FullParams, WhisperError> {
+        self.validate()?;
+        self.validate_prompt_tokens(n_vocab)?;
+
+        let language = self.language
+            .as_deref()
+            .map(CString::new)
+            .transpose()
+            .map_err(|_| WhisperError::InvalidParameter("Language contains a NUL byte".to_string()))?;
+
+        let initial_prompt = self.initial_prompt
+            .as_deref()
+            .map(CString::new)
+            .transpose()
+            .map_err(|_| WhisperError::InvalidParameter("Initial prompt contains a NUL byte".to_string()))?;
+
+        let prompt_tokens: Vec<c_int> = self.prompt_tokens.clone();
+
+        let vad_model_path = self.vad
+            .as_ref()
+            .map(|vad| CString::new(vad.model_path.as_str()))
+            .transpose()
+            .map_err(|_| WhisperError::InvalidParameter("VAD model path contains a NUL byte".to_string()))?;
+
+        let grammar = self.grammar.clone();
+        let grammar_rules = grammar.as_ref().map_or_else(Vec::new, Grammar::rule_pointers);
+
+        let strategy = match self.strategy {
+            SamplingStrategy::Greedy { .. } => 0, // WHISPER_SAMPLING_GREEDY
+            SamplingStrategy::BeamSearch { .. } => 1, // WHISPER_SAMPLING_BEAM_SEARCH
+        };
+        let mut params = unsafe { ffi::whisper_full_default_params(strategy) };
+
+        match self.strategy {
+            SamplingStrategy::Greedy { best_of } => params.greedy.best_of = best_of,
+            SamplingStrategy::BeamSearch { beam_size, patience } => {
+                params.beam_search.beam_size = beam_size;
+                params.beam_search.patience = patience;
+            }
+        }
+
+        params.n_threads = self.n_threads;
+        params.translate = self.translate;
+        params.no_context = self.no_context;
+        params.single_segment = self.single_segment;
+        params.temperature = self.temperature;
+        params.temperature_inc = self.temperature_inc;
+        params.entropy_thold = self.entropy_thold;
+        params.logprob_thold = self.logprob_thold;
+        params.no_speech_thold = self.no_speech_thold;
+        params.max_len = self.max_len;
+        params.split_on_word = self.split_on_word;
+        params.max_tokens = self.max_tokens;
+        params.audio_ctx = self.audio_ctx;
+        params.suppress_blank = self.suppress_blank;
+        params.suppress_nst = self.suppress_nst;
+
+        // Output handling owned by this crate
+        params.print_realtime = false;
+        params.print_progress = false;
+        params.print_timestamps = false;
+        params.print_special = false;
+        params.token_timestamps = true;
+
+        // Initialize all pointer fields to null for safety
+        params.language = language.as_ref().map_or(std::ptr::null(), |l| l.as_ptr());
+        params.initial_prompt = initial_prompt.as_ref().map_or(std::ptr::null(), |p| p.as_ptr());
+        if prompt_tokens.is_empty() {
+            params.prompt_tokens = null_mut();
+            params.prompt_n_tokens = 0;
+        } else {
+            params.prompt_tokens = prompt_tokens.as_ptr();
+            params.prompt_n_tokens = prompt_tokens.len() as c_int;
+        }
+        params.suppress_regex = null_mut();
+        params.new_segment_callback = null_mut();
+        params.new_segment_callback_user_data = null_mut();
+        params.progress_callback = null_mut();
+        params.progress_callback_user_data = null_mut();
+        params.encoder_begin_callback = null_mut();
+        params.encoder_begin_callback_user_data = null_mut();
+        params.abort_callback = null_mut();
+        params.abort_callback_user_data = null_mut();
+        // Boxed so the pointer handed to whisper.cpp stays put when FullParams moves
+        let logits_filter = self.logits_filter.clone().map(|filter| Box::new(LogitsFilterRun::new(filter)));
+        match &logits_filter {
+            Some(run) => {
+                params.logits_filter_callback = logits::logits_filter_callback as *mut c_void;
+                params.logits_filter_callback_user_data = &**run as *const LogitsFilterRun as *mut c_void;
+            }
+            None => {
+                params.logits_filter_callback = null_mut();
+                params.logits_filter_callback_user_data = null_mut();
+            }
+        }
+        match &grammar {
+            Some(grammar) => {
+                params.grammar_rules = grammar_rules.as_ptr();
+                params.n_grammar_rules = grammar_rules.len();
+                params.i_start_rule = grammar.rule_index(&self.grammar_rule).unwrap_or(0);
+                params.grammar_penalty = self.grammar_penalty;
+            }
+            None => {
+                params.grammar_rules = null_mut();
+                params.n_grammar_rules = 0;
+                params.i_start_rule = 0;
+                params.grammar_penalty = 0.0;
+            }
+        }
+        params.vad = self.vad.is_some();
+        params.vad_model_path = vad_model_path.as_ref().map_or(std::ptr::null(), |p| p.as_ptr());
+        params.vad_params = self.vad.clone().unwrap_or_default().to_raw();
+
+        Ok(FullParams {
+            raw: params,
+            _language: language,
+            _initial_prompt: initial_prompt,
+            _prompt_tokens: prompt_tokens,
+            _vad_model_path: vad_model_path,
+            _grammar: grammar,
+            _grammar_rules: grammar_rules,
+            logits_filter,
+            abort: None,
+        })
+    }
+}
+
+/// `whisper_full_params` together with the data its pointers refer to
+pub(crate) struct FullParams {
+    pub(crate) raw: ffi::WhisperFullParams,
+    _language: Option<CString>,
+    _initial_prompt: Option<CString>,
+    _prompt_tokens: Vec<c_int>,
+    _vad_model_path: Option<CString>,
+    // The rule pointers refer into the grammar's element vectors
+    _grammar: Option<Grammar>,
+    _grammar_rules: Vec<*const c_void>,
+    logits_filter: Option<Box<LogitsFilterRun>>,
+    abort: Option<Box<AbortSignal>>,
+}
+
+impl FullParams {
+    /// Install abort/encoder-begin callbacks polling `signal`. No-op for an inactive signal.
+    pub(crate) fn set_abort_signal(&mut self, signal: AbortSignal) {
+        if !signal.is_active() {
+            return;
+        }
+
+        // Boxed so the pointer handed to whisper.cpp stays put when FullParams moves
+        let signal = Box::new(signal);
+        let user_data = &*signal as *const AbortSignal as *mut c_void;
+        self.raw.abort_callback = cancel::abort_callback as *mut c_void;
+        self.raw.abort_callback_user_data = user_data;
+        self.raw.encoder_begin_callback = cancel::encoder_begin_callback as *mut c_void;
+        self.raw.encoder_begin_callback_user_data = user_data;
+        self.abort = Some(signal);
+    }
+
+    /// Forward progress and new segments to `callbacks`, which must outlive the run
+    pub(crate) fn set_callbacks(&mut self, callbacks: &mut Callbacks) {
+        let user_data = callbacks as *mut Callbacks as *mut c_void;
+        self.raw.progress_callback = callbacks::progress_callback as *mut c_void;
+        self.raw.progress_callback_user_data = user_data;
+        self.raw.new_segment_callback = callbacks::new_segment_callback as *mut c_void;
+        self.raw.new_segment_callback_user_data = user_data;
+    }
+
+    /// Re-raise a panic caught inside the logits filter
+    pub(crate) fn resume_logits_filter_panic(&self) {
+        if let Some(run) = &self.logits_filter {
+            run.resume_panic();
+        }
+    }
+
+    /// Why the last run was aborted, if it was
+    pub(crate) fn abort_reason(&self) -> Option<AbortReason> {
+        self.abort.as_ref().and_then(|signal| signal.reason())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_json_keeps_defaults() {
+        let options: TranscribeOptions = serde_json::from_str(
+            r#"{"language": "ar", "strategy": {"BeamSearch": {"beam_size": 3, "patience": -1.0}}}"#,
+        ).unwrap();
+
+        assert_eq!(options.language.as_deref(), Some("ar"));
+        assert_eq!(options.strategy, SamplingStrategy::BeamSearch { beam_size: 3, patience: -1.0 });
+        assert_eq!(options.n_threads, 4);
+        assert!(options.suppress_blank);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_values() {
+        assert!(TranscribeOptions::new().validate().is_ok());
+        assert!(TranscribeOptions::new().n_threads(0).validate().is_err());
+        assert!(TranscribeOptions::new().beam_search(0, -1.0).validate().is_err());
+        assert!(TranscribeOptions::new().temperature(-0.1).validate().is_err());
+    }
+
+    #[test]
+    fn test_prompt_tokens_must_be_in_the_vocabulary() {
+        assert!(TranscribeOptions::new().validate_prompt_tokens(0).is_ok());
+        assert!(TranscribeOptions::new().prompt_tokens(vec![0, 99]).validate_prompt_tokens(100).is_ok());
+        assert!(TranscribeOptions::new().prompt_tokens(vec![0, 100]).validate_prompt_tokens(100).is_err());
+        assert!(matches!(
+            TranscribeOptions::new().prompt_tokens(vec![-1]).validate_prompt_tokens(100),
+            Err(WhisperError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn test_grammar_start_rule_must_exist() {
+        let grammar = Grammar::from_phrases(&["yes", "no"]).unwrap();
+        let options = TranscribeOptions::new().grammar(grammar);
+        assert!(options.validate().is_ok());
+        assert!(options.clone().grammar_rule("command").validate().is_err());
+
+        let json = serde_json::to_string(&options).unwrap();
+        let parsed: TranscribeOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.grammar, options.grammar);
+    }
+
+    #[test]
+    fn test_vad_options_validate() {
+        assert!(VadOptions::default().validate().is_err());
+        assert!(VadOptions::new("silero.bin").validate().is_ok());
+        assert!(VadOptions::new("silero.bin").threshold(1.5).validate().is_err());
+        assert!(TranscribeOptions::new().vad(VadOptions::new("silero.bin").speech_pad_ms(-1)).validate().is_err());
+    }
+
+    #[test]
+    fn test_aheads_preset_from_hparams() {
+        assert_eq!(AlignmentHeadsPreset::from_hparams(51865, 4, 4, 80), Some(AlignmentHeadsPreset::Tiny));
+        assert_eq!(AlignmentHeadsPreset::from_hparams(51864, 6, 6, 80), Some(AlignmentHeadsPreset::BaseEn));
+        assert_eq!(AlignmentHeadsPreset::from_hparams(51866, 32, 32, 128), Some(AlignmentHeadsPreset::LargeV3));
+        assert_eq!(AlignmentHeadsPreset::from_hparams(51866, 32, 4, 128), Some(AlignmentHeadsPreset::LargeV3Turbo));
+        assert_eq!(AlignmentHeadsPreset::from_hparams(51865, 5, 5, 80), None);
+    }
+}
@@ -0,0 +1,384 @@
+//! Offline quantization of ggml whisper models
+//!
+//! Mirrors whisper.cpp's `quantize` tool: the header, mel filters and vocabulary
+//! are copied, every 2D weight tensor is quantized with `ggml_quantize_chunk`,
+//! and everything else is copied unchanged. The result is written next to the
+//! output path and only renamed into place once the header validator accepts it.
+
+use std::ffi::c_void;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::model_file::{ggml_type_size, HEADER_SIZE};
+use crate::{ffi, ModelFile, WhisperError};
+
+// Written into the header ftype as version * 1000 + ftype, like whisper.cpp
+const QNT_VERSION: i32 = 2;
+const QNT_VERSION_FACTOR: i32 = 1000;
+
+const GGML_TYPE_F32: i32 = 0;
+const GGML_TYPE_F16: i32 = 1;
+
+// Small tensors whisper.cpp keeps in full precision
+const SKIPPED_TENSORS: [&str; 4] = [
+    "encoder.conv1.bias",
+    "encoder.conv2.bias",
+    "encoder.positional_embedding",
+    "decoder.positional_embedding",
+];
+
+/// Target weight type for `quantize_model`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum QuantizationType {
+    Q5_0,
+    Q5_1,
+    Q8_0,
+}
+
+impl QuantizationType {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Q5_0 => "q5_0",
+            Self::Q5_1 => "q5_1",
+            Self::Q8_0 => "q8_0",
+        }
+    }
+
+    /// `enum ggml_type` of the quantized tensors
+    fn ggml_type(self) -> i32 {
+        match self {
+            Self::Q5_0 => 6,
+            Self::Q5_1 => 7,
+            Self::Q8_0 => 8,
+        }
+    }
+
+    /// `enum ggml_ftype` written to the model header
+    fn ftype(self) -> i32 {
+        match self {
+            Self::Q5_0 => 8,
+            Self::Q5_1 => 9,
+            Self::Q8_0 => 7,
+        }
+    }
+}
+
+impl fmt::Display for QuantizationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for QuantizationType {
+    type Err = WhisperError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "q5_0" => Ok(Self::Q5_0),
+            "q5_1" => Ok(Self::Q5_1),
+            "q8_0" => Ok(Self::Q8_0),
+            _ => Err(WhisperError::InvalidParameter(format!(
+                "Unknown quantization type '{}' (expected q5_0, q5_1 or q8_0)",
+                name
+            ))),
+        }
+    }
+}
+
+/// Outcome of `quantize_model`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantizeReport {
+    pub quantization: QuantizationType,
+    pub input_size: u64,
+    pub output_size: u64,
+    pub quantized_tensors: usize,
+    pub copied_tensors: usize,
+    /// The output as read back by the header validator
+    pub output: ModelFile,
+}
+
+/// Convert an f32/f16 ggml whisper model at `input` into `quantization` at `output`
+pub fn quantize_model(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    quantization: QuantizationType,
+) -> Result<QuantizeReport, WhisperError> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+
+    let source = ModelFile::inspect(input)?;
+    if !matches!(source.hparams.ftype, 0 | 1) {
+        return Err(WhisperError::InvalidParameter(format!(
+            "{} is already quantized (ftype {}); quantize an f32 or f16 model",
+            input.display(),
+            source.hparams.ftype
+        )));
+    }
+
+    // Write beside the output so a failed run never leaves a half-written model in place
+    let partial = partial_path(output);
+    let result = write_quantized(input, &partial, &source, quantization);
+    let (quantized_tensors, copied_tensors) = match result {
+        Ok(counts) => counts,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+
+    let written = match ModelFile::inspect(&partial) {
+        Ok(written) if written.hparams.ftype == quantization.ftype() && written.n_tensors == source.n_tensors => written,
+        Ok(_) => {
+            let _ = fs::remove_file(&partial);
+            return Err(WhisperError::InternalError("Quantized model doesn't match its source".to_string()));
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+    fs::rename(&partial, output).map_err(|e| io_error(output, e))?;
+
+    Ok(QuantizeReport {
+        quantization,
+        input_size: source.file_size,
+        output_size: written.file_size,
+        quantized_tensors,
+        copied_tensors,
+        output: written,
+    })
+}
+
+fn partial_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    output.with_file_name(name)
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> WhisperError {
+    WhisperError::InternalError(format!("I/O error on {}: {}", path.display(), e))
+}
+
+/// Returns (quantized, copied) tensor counts
+fn write_quantized(
+    input: &Path,
+    output: &Path,
+    source: &ModelFile,
+    quantization: QuantizationType,
+) -> Result<(usize, usize), WhisperError> {
+    let mut reader = BufReader::new(File::open(input).map_err(|e| io_error(input, e))?);
+    let mut writer = BufWriter::new(File::create(output).map_err(|e| io_error(output, e))?);
+    let mut copy = Copier { reader: &mut reader, writer: &mut writer, input, output };
+
+    // Header, with the new ftype in the last hyperparameter
+    let mut header = copy.read(HEADER_SIZE)?;
+    let ftype = QNT_VERSION * QNT_VERSION_FACTOR + quantization.ftype();
+    header[HEADER_SIZE - 4..].copy_from_slice(&ftype.to_le_bytes());
+    copy.write(&header)?;
+
+    // Mel filters and vocabulary are copied as they are
+    let n_mel = copy.copy_i32()?;
+    let n_fft = copy.copy_i32()?;
+    copy.copy_bytes(n_mel as usize * n_fft as usize * 4)?;
+
+    let n_tokens = copy.copy_i32()?;
+    for _ in 0..n_tokens {
+        let len = copy.copy_i32()?;
+        copy.copy_bytes(len as usize)?;
+    }
+
+    let (mut quantized, mut copied) = (0, 0);
+    for _ in 0..source.n_tensors {
+        if copy.copy_tensor(quantization)? {
+            quantized += 1;
+        } else {
+            copied += 1;
+        }
+    }
+
+    writer.flush().map_err(|e| io_error(output, e))?;
+    Ok((quantized, copied))
+}
+
+struct Copier<'a, R, W> {
+    reader: &'a mut R,
+    writer: &'a mut W,
+    input: &'a Path,
+    output: &'a Path,
+}
+
+impl<R: Read, W: Write> Copier<'_, R, W> {
+    fn read(&mut self, len: usize) -> Result<Vec<u8>, WhisperError> {
+        let mut bytes = vec![0u8; len];
+        self.reader.read_exact(&mut bytes).map_err(|e| io_error(self.input, e))?;
+        Ok(bytes)
+    }
+
+    fn read_i32(&mut self) -> Result<i32, WhisperError> {
+        let bytes = self.read(4)?;
+        Ok(i32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), WhisperError> {
+        self.writer.write_all(bytes).map_err(|e| io_error(self.output, e))
+    }
+
+    fn copy_i32(&mut self) -> Result<i32, WhisperError> {
+        let value = self.read_i32()?;
+        self.write(&value.to_le_bytes())?;
+        Ok(value)
+    }
+
+    fn copy_bytes(&mut self, len: usize) -> Result<(), WhisperError> {
+        let bytes = self.read(len)?;
+        self.write(&bytes)
+    }
+
+    /// Copy one tensor, quantizing it if it is a 2D weight; returns whether it was quantized
+    fn copy_tensor(&mut self, quantization: QuantizationType) -> Result<bool, WhisperError> {
+        let n_dims = self.read_i32()?;
+        let name_len = self.read_i32()?;
+        let ttype = self.read_i32()?;
+        let mut ne = Vec::with_capacity(n_dims as usize);
+        for _ in 0..n_dims {
+            ne.push(self.read_i32()?);
+        }
+        let name_bytes = self.read(name_len as usize)?;
+        let name = String::from_utf8_lossy(&name_bytes).to_string();
+
+        let n_elements: usize = ne.iter().map(|&n| n as usize).product();
+        let (block_size, block_len) = ggml_type_size(ttype)
+            .ok_or_else(|| WhisperError::InvalidModel(format!("Tensor '{}' has unknown type {}", name, ttype)))?;
+        let data = self.read(n_elements / block_len as usize * block_size as usize)?;
+
+        let quantize = n_dims == 2 && !SKIPPED_TENSORS.contains(&name.as_str());
+        let (ttype, data) = if quantize {
+            (quantization.ggml_type(), quantize_tensor(&name, ttype, &data, ne[0] as usize, quantization)?)
+        } else {
+            (ttype, data)
+        };
+
+        for value in [n_dims, name_len, ttype] {
+            self.write(&value.to_le_bytes())?;
+        }
+        for n in ne {
+            self.write(&n.to_le_bytes())?;
+        }
+        self.write(&name_bytes)?;
+        self.write(&data)?;
+
+        Ok(quantize)
+    }
+}
+
+fn quantize_tensor(
+    name: &str,
+    ttype: i32,
+    data: &[u8],
+    row_len: usize,
+    quantization: QuantizationType,
+) -> Result<Vec<u8>, WhisperError> {
+    let values: Vec<f32> = match ttype {
+        GGML_TYPE_F32 => data.chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect(),
+        GGML_TYPE_F16 => {
+            let halves: Vec<u16> = data.chunks_exact(2).map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap())).collect();
+            let mut values = vec![0f32; halves.len()];
+            unsafe { ffi::ggml_fp16_to_fp32_row(halves.as_ptr(), values.as_mut_ptr(), halves.len() as i64) };
+            values
+        }
+        _ => {
+            return Err(WhisperError::InvalidModel(format!(
+                "Tensor '{}' has type {}; only f32 and f16 tensors can be quantized",
+                name, ttype
+            )))
+        }
+    };
+
+    let (block_size, block_len) = ggml_type_size(quantization.ggml_type()).unwrap();
+    if row_len == 0 || !row_len.is_multiple_of(block_len as usize) {
+        return Err(WhisperError::InvalidModel(format!(
+            "Tensor '{}' rows of {} values can't be split into {} blocks",
+            name, row_len, quantization
+        )));
+    }
+
+    let n_rows = values.len() / row_len;
+    let mut quantized = vec![0u8; values.len() / block_len as usize * block_size as usize];
+    let written = unsafe {
+        ffi::ggml_quantize_chunk(
+            quantization.ggml_type(),
+            values.as_ptr(),
+            quantized.as_mut_ptr() as *mut c_void,
+            0,
+            n_rows as i64,
+            row_len as i64,
+            std::ptr::null(),
+        )
+    };
+    if written != quantized.len() {
+        return Err(WhisperError::InternalError(format!(
+            "Quantizing '{}' produced {} bytes, expected {}",
+            name,
+            written,
+            quantized.len()
+        )));
+    }
+
+    Ok(quantized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_file::test_model;
+
+    /// Append a 2D f32 tensor of `rows` rows of 32 values
+    fn push_matrix(model: &mut Vec<u8>, name: &str, rows: i32) {
+        for value in [2, name.len() as i32, GGML_TYPE_F32, 32, rows] {
+            model.extend_from_slice(&value.to_le_bytes());
+        }
+        model.extend_from_slice(name.as_bytes());
+        for i in 0..32 * rows {
+            model.extend_from_slice(&(i as f32 / 64.0).to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_quantize_rewrites_header_and_validates() {
+        let dir = std::env::temp_dir().join(format!("whisper-quantize-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("ggml-tiny.bin"), dir.join("ggml-tiny-q5_0.bin"));
+
+        // A 1D tensor, a 2D tensor on the skip list and a 2D weight
+        let mut model = test_model();
+        push_matrix(&mut model, "encoder.positional_embedding", 2);
+        let weight_offset = model.len();
+        push_matrix(&mut model, "decoder.blocks.0.mlp.0.weight", 2);
+        fs::write(&input, &model).unwrap();
+
+        let report = quantize_model(&input, &output, "Q5_0".parse().unwrap()).unwrap();
+        assert_eq!((report.quantized_tensors, report.copied_tensors), (1, 2));
+        assert_eq!(report.output.hparams.ftype, 8);
+        assert!(!partial_path(&output).exists());
+
+        // Only the weight shrinks: 64 f32 values become two 22-byte q5_0 blocks
+        assert_eq!(report.output_size, report.input_size - 64 * 4 + 2 * 22);
+        let written = ModelFile::inspect(&output).unwrap();
+        assert_eq!((written.hparams.ftype, written.n_tensors), (8, 3));
+
+        // Everything between the header and the weight is unchanged; the weight's type is now q5_0
+        let bytes = fs::read(&output).unwrap();
+        assert_eq!(bytes[HEADER_SIZE..weight_offset], model[HEADER_SIZE..weight_offset]);
+        let ttype = i32::from_le_bytes(bytes[weight_offset + 8..weight_offset + 12].try_into().unwrap());
+        assert_eq!(ttype, QuantizationType::Q5_0.ggml_type());
+
+        // Re-quantizing a quantized model is refused
+        let err = quantize_model(&output, dir.join("again.bin"), QuantizationType::Q8_0).unwrap_err();
+        assert!(matches!(err, WhisperError::InvalidParameter(_)));
+        assert!("q4_k".parse::<QuantizationType>().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,6 @@
 use crate::flutter_api::*;
+use crate::TranscribeOptions;
+use crate::error_code::catch_panic;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 
@@ -105,6 +107,8 @@ impl IntegratedFlutterApi {
             window_duration_ms: session_config.window_duration_ms,
             overlap_duration_ms: session_config.overlap_duration_ms,
             chunk_size_ms: 50,
+            options: TranscribeOptions::default(),
+            skip_silence: true,
         };
         
         FlutterTranscriberApi::create_transcriber(instance_id.clone(), config)?;
@@ -186,24 +190,22 @@ impl Default for FrbQuranSessionConfig {
 pub extern "C" fn whisper_register_quran_validator(
     callback: ExternalValidationCallback
 ) -> *const c_char {
-    match IntegratedFlutterApi::register_external_validator(callback) {
-        Ok(msg) => {
-            let c_str = CString::new(msg).unwrap();
-            c_str.into_raw()
-        }
-        Err(e) => {
-            let c_str = CString::new(format!("❌ Error: {}", e)).unwrap();
-            c_str.into_raw()
-        }
-    }
+    catch_panic(std::ptr::null(), || {
+        let msg = match IntegratedFlutterApi::register_external_validator(callback) {
+            Ok(msg) => msg,
+            Err(e) => format!("❌ Error: {}", e),
+        };
+        // NULL rather than a panic if the message can't be a C string
+        CString::new(msg).map_or(std::ptr::null(), |c_str| c_str.into_raw())
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn whisper_free_string(s: *mut c_char) {
     if !s.is_null() {
-        unsafe {
+        catch_panic((), || unsafe {
             let _ = CString::from_raw(s);
-        }
+        });
     }
 }
 
@@ -1,8 +1,9 @@
 use std::collections::VecDeque;
-use std::sync::{Arc, Mutex, mpsc};
+use std::sync::{Arc, mpsc};
+use parking_lot::Mutex;
 use std::thread;
 use std::time::{Duration, Instant};
-use crate::{init_whisper, process_audio, free_whisper};
+use crate::{ContextOptions, Segment, Session, TranscribeOptions, VadConfig, VadOptions, VoiceActivityDetector, Whisper};
 
 #[derive(Debug, Clone)]
 pub struct TranscriptionSegment {
@@ -35,8 +36,8 @@ pub struct RealTimeTranscriber {
     // Audio buffer with overlap management
     audio_buffer: Arc<Mutex<VecDeque<f32>>>,
     
-    // Whisper instance
-    whisper_instance: Option<i32>,
+    // Loaded model; the processing thread decodes on its own session
+    whisper: Whisper,
     
     // Configuration
     sample_rate: usize,
@@ -55,6 +56,12 @@ pub struct RealTimeTranscriber {
     
     // Buffer management
     max_buffer_duration: f64,
+    
+    // Silero VAD applied to each window before decoding
+    vad: Option<VadOptions>,
+    
+    // Built-in detector that keeps silent windows away from whisper
+    speech_gate: Option<VadConfig>,
 }
 
 impl RealTimeTranscriber {
@@ -71,15 +78,15 @@ impl RealTimeTranscriber {
         println!("   - Overlap: {:.1}s", overlap_duration);
         println!("   - Max buffer: {:.1}s", max_buffer_duration);
         
-        // Initialize Whisper
-        let whisper_instance = init_whisper(model_path)?;
-        println!("   ✅ Whisper model loaded (ID: {})", whisper_instance);
+        // Initialize Whisper with DTW word timestamps
+        let whisper = Whisper::load(model_path, ContextOptions::with_word_timestamps())?;
+        println!("   ✅ Whisper model loaded");
         
         let max_buffer_samples = (sample_rate as f64 * max_buffer_duration) as usize;
         
         Ok(RealTimeTranscriber {
             audio_buffer: Arc::new(Mutex::new(VecDeque::with_capacity(max_buffer_samples))),
-            whisper_instance: Some(whisper_instance),
+            whisper,
             sample_rate,
             window_duration,
             overlap_duration,
@@ -88,12 +95,29 @@ impl RealTimeTranscriber {
             validation_sender: None,
             processing_handle: None,
             max_buffer_duration,
+            vad: None,
+            speech_gate: Some(VadConfig {
+                sample_rate: sample_rate as u32,
+                ..VadConfig::default()
+            }),
         })
     }
     
+    /// Replace the detector that skips silent windows; `None` transcribes every window
+    pub fn with_speech_gate(mut self, config: Option<VadConfig>) -> Self {
+        self.speech_gate = config;
+        self
+    }
+    
+    /// Skip silent audio with whisper's Silero VAD (set before `start_processing`)
+    pub fn with_vad(mut self, vad: VadOptions) -> Self {
+        self.vad = Some(vad);
+        self
+    }
+    
     /// Add audio data from Flutter Record (called continuously)
     pub fn add_audio_chunk(&mut self, audio_data: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
-        let mut buffer = self.audio_buffer.lock().unwrap();
+        let mut buffer = self.audio_buffer.lock();
         
         // Add new samples
         for &sample in audio_data {
@@ -111,7 +135,7 @@ impl RealTimeTranscriber {
     
     /// Get current buffer duration in seconds
     pub fn get_buffer_duration(&self) -> f64 {
-        let buffer = self.audio_buffer.lock().unwrap();
+        let buffer = self.audio_buffer.lock();
         buffer.len() as f64 / self.sample_rate as f64
     }
     
@@ -131,15 +155,19 @@ impl RealTimeTranscriber {
         
         let buffer_clone = Arc::clone(&self.audio_buffer);
         let last_processed_clone = Arc::clone(&self.last_processed_time);
-        let whisper_instance = self.whisper_instance.unwrap();
+        let session = self.whisper.session();
         let sample_rate = self.sample_rate;
         let window_duration = self.window_duration;
         let overlap_duration = self.overlap_duration;
+        let mut options = TranscribeOptions::for_language(Some("ar"));
+        options.vad = self.vad.clone();
+        let speech_gate = self.speech_gate.clone().map(VoiceActivityDetector::new);
         
         // Create communication channels
         let (tx_transcription, rx_transcription) = mpsc::channel();
         let (tx_validation, rx_validation) = mpsc::channel();
         
+        let segment_sender = tx_transcription.clone();
         self.transcription_sender = Some(tx_transcription);
         self.validation_sender = Some(tx_validation);
         
@@ -148,7 +176,10 @@ impl RealTimeTranscriber {
             Self::processing_loop(
                 buffer_clone,
                 last_processed_clone,
-                whisper_instance,
+                segment_sender,
+                session,
+                options,
+                speech_gate,
                 sample_rate,
                 window_duration,
                 overlap_duration,
@@ -175,10 +206,14 @@ impl RealTimeTranscriber {
     }
     
     /// Main processing loop
+    #[allow(clippy::too_many_arguments)]
     fn processing_loop(
         buffer: Arc<Mutex<VecDeque<f32>>>,
         last_processed_time: Arc<Mutex<f64>>,
-        whisper_instance: i32,
+        segment_sender: mpsc::Sender<TranscriptionSegment>,
+        mut session: Session,
+        options: TranscribeOptions,
+        mut speech_gate: Option<VoiceActivityDetector>,
         sample_rate: usize,
         window_duration: f64,
         overlap_duration: f64,
@@ -194,19 +229,19 @@ impl RealTimeTranscriber {
         
         loop {
             let current_time = {
-                let buffer_guard = buffer.lock().unwrap();
+                let buffer_guard = buffer.lock();
                 buffer_guard.len() as f64 / sample_rate as f64
             };
             
             let last_processed = {
-                let last_guard = last_processed_time.lock().unwrap();
+                let last_guard = last_processed_time.lock();
                 *last_guard
             };
             
             // Check if we have enough data for next window
             if current_time - last_processed >= hop_duration && current_time >= window_duration {
                 let audio_window = {
-                    let buffer_guard = buffer.lock().unwrap();
+                    let buffer_guard = buffer.lock();
                     if buffer_guard.len() >= window_samples {
                         // Extract latest window
                         let start_idx = buffer_guard.len() - window_samples;
@@ -221,20 +256,41 @@ impl RealTimeTranscriber {
                 
                 println!("🎬 Processing window [{:.1}s - {:.1}s]", window_start_time, window_end_time);
                 
+                // Silent windows (per the built-in detector) never reach whisper
+                let has_speech = speech_gate
+                    .as_mut()
+                    .is_none_or(|detector| detector.contains_speech(&audio_window));
+                
                 // Process with Whisper
                 let process_start = Instant::now();
-                match process_audio(whisper_instance, &audio_window, Some("ar")) {
-                    Ok(result) => {
+                let result = if has_speech {
+                    session.transcribe(&audio_window, &options)
+                } else {
+                    Ok(Vec::new())
+                };
+                match result {
+                    Ok(segments) => {
                         let process_time = process_start.elapsed();
                         let rtf = process_time.as_secs_f64() / window_duration;
+                        let spoken: Vec<&Segment> = segments
+                            .iter()
+                            .filter(|segment| !segment.text.trim().is_empty())
+                            .collect();
                         
-                        if !result.trim().is_empty() {
-                            let combined_text = result.trim();
+                        if !spoken.is_empty() {
+                            let combined_text = spoken
+                                .iter()
+                                .map(|segment| segment.text.trim())
+                                .collect::<Vec<_>>()
+                                .join(" ");
                             
                             println!("   ✅ Transcribed: '{}' ({:.3}s, {:.1}x RT)", 
                                     combined_text, process_time.as_secs_f64(), 1.0 / rtf);
                             
-                            // TODO: Send to transcription callback
+                            for segment in spoken {
+                                let _ = segment_sender.send(Self::to_transcription_segment(segment, window_start_time));
+                            }
+                            
                             // TODO: Send individual words for validation
                             
                         } else {
@@ -248,7 +304,7 @@ impl RealTimeTranscriber {
                 
                 // Update last processed time
                 {
-                    let mut last_guard = last_processed_time.lock().unwrap();
+                    let mut last_guard = last_processed_time.lock();
                     *last_guard = current_time - overlap_duration;
                 }
             }
@@ -258,6 +314,29 @@ impl RealTimeTranscriber {
         }
     }
     
+    /// Convert a whisper segment to stream time (seconds since the buffer start)
+    fn to_transcription_segment(segment: &Segment, window_start_time: f64) -> TranscriptionSegment {
+        let to_seconds = |ms: i64| window_start_time + ms as f64 / 1000.0;
+        
+        TranscriptionSegment {
+            text: segment.text.trim().to_string(),
+            start_time: to_seconds(segment.t0_ms),
+            end_time: to_seconds(segment.t1_ms),
+            confidence: segment.confidence() as f64,
+            words: segment
+                .words()
+                .into_iter()
+                .map(|word| WordSegment {
+                    word: word.text,
+                    start_time: to_seconds(word.t0_ms),
+                    end_time: to_seconds(word.t1_ms),
+                    confidence: word.probability as f64,
+                    validated: false,
+                })
+                .collect(),
+        }
+    }
+    
     /// Validate transcribed text against expected content
     pub fn validate_text(&self, transcribed: &str, expected: &str) -> ValidationResult {
         // Simple word-level validation (can be enhanced with fuzzy matching)
@@ -286,15 +365,6 @@ impl RealTimeTranscriber {
     }
 }
 
-impl Drop for RealTimeTranscriber {
-    fn drop(&mut self) {
-        if let Some(instance_id) = self.whisper_instance.take() {
-            let _ = free_whisper(instance_id);
-            println!("🧹 Whisper instance cleaned up");
-        }
-    }
-}
-
 // Flutter-Rust Bridge compatible functions
 pub fn create_realtime_transcriber(
     model_path: String,
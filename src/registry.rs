@@ -0,0 +1,127 @@
+//! Instance registry behind the integer ids handed out over the C API
+//!
+//! The map lock is only held long enough to clone an entry's `Arc`. Work on an
+//! instance locks that instance alone, so a long transcription never blocks
+//! init/free/is_valid or calls on other instances.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use parking_lot::Mutex;
+
+pub(crate) struct Registry<T> {
+    entries: Mutex<HashMap<i32, Arc<Mutex<T>>>>,
+    next_id: AtomicI32,
+}
+
+impl<T> Registry<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            next_id: AtomicI32::new(0),
+        }
+    }
+
+    /// Store a value and return its new id
+    pub(crate) fn insert(&self, value: T) -> i32 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.entries.lock().insert(id, Arc::new(Mutex::new(value)));
+        id
+    }
+
+    /// Clone out an entry; the map lock is released before this returns
+    pub(crate) fn get(&self, id: i32) -> Option<Arc<Mutex<T>>> {
+        self.entries.lock().get(&id).cloned()
+    }
+
+    /// Remove an entry. A call already running on it keeps its own `Arc`,
+    /// so the value is dropped once that call finishes.
+    pub(crate) fn remove(&self, id: i32) -> Option<Arc<Mutex<T>>> {
+        self.entries.lock().remove(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Stands in for WhisperContext: "decoding" only finishes once every
+    // instance is decoding at the same time
+    struct FakeInstance {
+        running: Arc<AtomicUsize>,
+    }
+
+    impl FakeInstance {
+        fn decode(&mut self, peers: usize) -> bool {
+            self.running.fetch_add(1, Ordering::SeqCst);
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while self.running.load(Ordering::SeqCst) < peers {
+                if Instant::now() >= deadline {
+                    return false;
+                }
+                thread::yield_now();
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn test_instances_decode_in_parallel() {
+        let registry = Arc::new(Registry::new());
+        let running = Arc::new(AtomicUsize::new(0));
+        let ids: Vec<i32> = (0..2)
+            .map(|_| registry.insert(FakeInstance { running: running.clone() }))
+            .collect();
+
+        let workers: Vec<_> = ids
+            .iter()
+            .map(|&id| {
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    let instance = registry.get(id).unwrap();
+                    let mut instance = instance.lock();
+                    instance.decode(2)
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            assert!(worker.join().unwrap(), "instances were serialized");
+        }
+    }
+
+    #[test]
+    fn test_busy_instance_does_not_block_registry() {
+        let registry = Registry::new();
+        let busy = registry.insert(1);
+        let entry = registry.get(busy).unwrap();
+        let _guard = entry.lock();
+
+        let other = registry.insert(2);
+        assert_eq!(*registry.get(other).unwrap().lock(), 2);
+        assert!(registry.remove(busy).is_some());
+        assert!(registry.get(busy).is_none());
+    }
+
+    #[test]
+    fn test_entry_usable_after_panic_while_locked() {
+        let registry = Arc::new(Registry::new());
+        let id = registry.insert(0);
+
+        let panicking = registry.clone();
+        let result = thread::spawn(move || {
+            let entry = panicking.get(id).unwrap();
+            let mut value = entry.lock();
+            *value = 1;
+            panic!("decode failed");
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert_eq!(*registry.get(id).unwrap().lock(), 1);
+        assert!(registry.remove(id).is_some());
+    }
+}
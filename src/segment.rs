@@ -0,0 +1,257 @@
+//! Timestamped transcription results
+
+use serde::{Deserialize, Serialize};
+
+/// A transcribed segment together with its position in the input audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub text: String,
+    /// Start of the segment in milliseconds, relative to the start of the audio
+    pub t0_ms: i64,
+    /// End of the segment in milliseconds, relative to the start of the audio
+    pub t1_ms: i64,
+    /// Probability that the segment contains no speech at all
+    pub no_speech_prob: f32,
+    /// Tokens decoded for this segment, including special/timestamp tokens
+    pub tokens: Vec<TokenData>,
+}
+
+/// Per-token decoding data reported by whisper.cpp
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenData {
+    pub id: i32,
+    /// Token text; may be a partial UTF-8 sequence for non-Latin scripts
+    pub text: String,
+    /// Probability of the token
+    pub p: f32,
+    /// Log probability of the token
+    pub plog: f32,
+    pub t0_ms: i64,
+    pub t1_ms: i64,
+    /// DTW-aligned token time, only available when DTW is enabled on the context
+    pub t_dtw_ms: Option<i64>,
+    /// Special tokens (timestamps, [_BEG_], ...) do not contribute to the text
+    pub special: bool,
+    /// Raw token bytes, kept so words can be rebuilt without lossy UTF-8 splits
+    #[serde(skip)]
+    pub(crate) bytes: Vec<u8>,
+}
+
+/// A word rebuilt from consecutive text tokens
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Word {
+    pub text: String,
+    pub t0_ms: i64,
+    pub t1_ms: i64,
+    /// Mean probability of the tokens forming the word
+    pub probability: f32,
+}
+
+/// A stretch of audio a voice activity detector classified as speech
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpeechRegion {
+    pub start_ms: i64,
+    pub end_ms: i64,
+}
+
+impl SpeechRegion {
+    pub fn duration_ms(&self) -> i64 {
+        (self.end_ms - self.start_ms).max(0)
+    }
+}
+
+/// Segments together with the speech regions VAD found in the same audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub segments: Vec<Segment>,
+    pub speech_regions: Vec<SpeechRegion>,
+}
+
+impl Segment {
+    /// Duration of the segment in milliseconds
+    pub fn duration_ms(&self) -> i64 {
+        (self.t1_ms - self.t0_ms).max(0)
+    }
+
+    /// Shift the segment by `offset_ms` (used when a window starts mid-stream)
+    pub fn offset(mut self, offset_ms: i64) -> Self {
+        self.t0_ms += offset_ms;
+        self.t1_ms += offset_ms;
+        for token in &mut self.tokens {
+            token.t0_ms += offset_ms;
+            token.t1_ms += offset_ms;
+            token.t_dtw_ms = token.t_dtw_ms.map(|t| t + offset_ms);
+        }
+        self
+    }
+
+    /// All tokens of the segment, including special ones
+    pub fn tokens(&self) -> &[TokenData] {
+        &self.tokens
+    }
+
+    /// Tokens that make up the segment text
+    pub fn text_tokens(&self) -> impl Iterator<Item = &TokenData> {
+        self.tokens.iter().filter(|token| !token.special)
+    }
+
+    /// Mean token probability of the segment, or 0.0 if it has no text tokens
+    pub fn confidence(&self) -> f32 {
+        mean_probability(self.text_tokens())
+    }
+
+    /// Group text tokens into words; a token starting with a space begins a new word.
+    ///
+    /// When every token carries a DTW time, a word spans from the DTW time of its
+    /// first token to the DTW time of the next word (the segment end for the last
+    /// word). Otherwise whisper's token timestamps are used.
+    pub fn words(&self) -> Vec<Word> {
+        let mut groups: Vec<Vec<&TokenData>> = Vec::new();
+
+        for token in self.text_tokens() {
+            let starts_word = token.bytes.first() == Some(&b' ');
+            match groups.last_mut() {
+                Some(group) if !starts_word => group.push(token),
+                _ => groups.push(vec![token]),
+            }
+        }
+
+        groups.retain(|group| {
+            let bytes: Vec<u8> = group.iter().flat_map(|token| token.bytes.iter().copied()).collect();
+            !String::from_utf8_lossy(&bytes).trim().is_empty()
+        });
+
+        let use_dtw = !groups.is_empty()
+            && groups.iter().flatten().all(|token| token.t_dtw_ms.is_some());
+        let starts: Vec<Option<i64>> = groups
+            .iter()
+            .map(|group| group.first().and_then(|token| token.t_dtw_ms))
+            .collect();
+
+        groups
+            .iter()
+            .enumerate()
+            .filter_map(|(i, group)| {
+                let bytes: Vec<u8> = group.iter().flat_map(|token| token.bytes.iter().copied()).collect();
+                let first = group.first()?;
+                let last = group.last()?;
+
+                let (t0_ms, t1_ms) = if use_dtw {
+                    let t0 = starts[i]?;
+                    let t1 = starts.get(i + 1).copied().flatten().unwrap_or(self.t1_ms);
+                    (t0, t1.max(t0))
+                } else {
+                    (first.t0_ms, last.t1_ms)
+                };
+
+                Some(Word {
+                    text: String::from_utf8_lossy(&bytes).trim().to_string(),
+                    t0_ms,
+                    t1_ms,
+                    probability: mean_probability(group.iter().copied()),
+                })
+            })
+            .collect()
+    }
+}
+
+fn mean_probability<'a>(tokens: impl Iterator<Item = &'a TokenData>) -> f32 {
+    let (sum, count) = tokens.fold((0.0f32, 0usize), |(sum, count), token| (sum + token.p, count + 1));
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f32
+    }
+}
+
+/// Join segment texts the same way `process_audio` always has (one segment per line)
+pub fn segments_to_text(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(|s| s.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(bytes: &[u8], p: f32, t0_ms: i64, t1_ms: i64, special: bool) -> TokenData {
+        TokenData {
+            id: 0,
+            text: String::from_utf8_lossy(bytes).to_string(),
+            p,
+            plog: p.ln(),
+            t0_ms,
+            t1_ms,
+            t_dtw_ms: None,
+            special,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_words_rebuild_split_utf8() {
+        // "الله" split mid-character across two tokens, as byte-level BPE does
+        let allah = " الله".as_bytes();
+        let segment = Segment {
+            text: " الله اكبر".to_string(),
+            t0_ms: 0,
+            t1_ms: 1000,
+            no_speech_prob: 0.0,
+            tokens: vec![
+                token(b"[_BEG_]", 1.0, 0, 0, true),
+                token(&allah[..4], 0.8, 0, 200, false),
+                token(&allah[4..], 0.6, 200, 400, false),
+                token(" اكبر".as_bytes(), 0.9, 400, 1000, false),
+            ],
+        };
+
+        let words = segment.words();
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[0].text, "الله");
+        assert_eq!(words[0].t0_ms, 0);
+        assert_eq!(words[0].t1_ms, 400);
+        assert!((words[0].probability - 0.7).abs() < 1e-6);
+        assert_eq!(words[1].text, "اكبر");
+        assert!((segment.confidence() - (0.8 + 0.6 + 0.9) / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_offset_shifts_tokens() {
+        let segment = Segment {
+            text: " hi".to_string(),
+            t0_ms: 100,
+            t1_ms: 300,
+            no_speech_prob: 0.1,
+            tokens: vec![token(b" hi", 0.9, 100, 300, false)],
+        }
+        .offset(1000);
+
+        assert_eq!((segment.t0_ms, segment.t1_ms), (1100, 1300));
+        assert_eq!((segment.tokens[0].t0_ms, segment.tokens[0].t1_ms), (1100, 1300));
+    }
+
+    #[test]
+    fn test_words_use_dtw_times() {
+        let mut tokens = vec![
+            token(b" one", 0.9, 0, 500, false),
+            token(b" two", 0.9, 500, 1000, false),
+        ];
+        tokens[0].t_dtw_ms = Some(120);
+        tokens[1].t_dtw_ms = Some(610);
+
+        let segment = Segment {
+            text: " one two".to_string(),
+            t0_ms: 0,
+            t1_ms: 1000,
+            no_speech_prob: 0.0,
+            tokens,
+        };
+
+        let words = segment.words();
+        assert_eq!((words[0].t0_ms, words[0].t1_ms), (120, 610));
+        assert_eq!((words[1].t0_ms, words[1].t1_ms), (610, 1000));
+    }
+}
@@ -0,0 +1,177 @@
+//! The vocabulary of a loaded model: text to token ids and back
+//!
+//! Token ids are what `TranscribeOptions::prompt_tokens` and the decoder work
+//! with. Ids at or above EOT are special tokens (SOT, languages, tasks, timestamps)
+//! and have no text of their own.
+
+use std::ffi::{c_int, CStr, CString};
+use std::fmt;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::{ffi, Model, WhisperError};
+
+/// Index into a model's vocabulary
+pub type TokenId = i32;
+
+/// Timestamp tokens are 20 ms apart, from `timestamp_begin` (0 ms) up to 30 s
+const TIMESTAMP_STEP_MS: i64 = 20;
+const MAX_TIMESTAMP_MS: i64 = 30_000;
+
+/// Ids of the special tokens of a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecialTokens {
+    /// End of transcript; every id from here on is special
+    pub eot: TokenId,
+    /// Start of transcript
+    pub sot: TokenId,
+    /// Marks the previous-text prompt
+    pub sot_prev: TokenId,
+    pub sot_lm: TokenId,
+    pub no_speech: TokenId,
+    pub no_timestamps: TokenId,
+    /// Timestamp 0 ms; later timestamps follow in 20 ms steps
+    pub timestamp_begin: TokenId,
+    pub translate: TokenId,
+    pub transcribe: TokenId,
+}
+
+/// Tokenizer over a loaded model's vocabulary. Clones share the model, which
+/// stays loaded while any tokenizer uses it.
+#[derive(Clone)]
+pub struct Tokenizer {
+    model: Arc<Model>,
+}
+
+impl fmt::Debug for Tokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tokenizer")
+            .field("model_path", &self.model.model_path())
+            .field("n_vocab", &self.n_vocab())
+            .finish()
+    }
+}
+
+impl Tokenizer {
+    pub fn new(model: Arc<Model>) -> Self {
+        Self { model }
+    }
+
+    /// Number of tokens, special tokens included
+    pub fn n_vocab(&self) -> usize {
+        unsafe { ffi::whisper_n_vocab(self.model.ctx()) }.max(0) as usize
+    }
+
+    /// Split text into token ids the way whisper.cpp encodes prompts
+    pub fn tokenize(&self, text: &str) -> Result<Vec<TokenId>, WhisperError> {
+        let text_c = CString::new(text)
+            .map_err(|_| WhisperError::InvalidParameter("Text contains a NUL byte".to_string()))?;
+
+        let n_tokens = unsafe { ffi::whisper_token_count(self.model.ctx(), text_c.as_ptr()) };
+        let mut tokens = vec![0 as TokenId; n_tokens.max(0) as usize];
+        if tokens.is_empty() {
+            return Ok(tokens);
+        }
+
+        let written = unsafe {
+            ffi::whisper_tokenize(self.model.ctx(), text_c.as_ptr(), tokens.as_mut_ptr(), tokens.len() as c_int)
+        };
+        if written < 0 {
+            return Err(WhisperError::ProcessingError(format!("Failed to tokenize text: {}", written)));
+        }
+        tokens.truncate(written as usize);
+
+        Ok(tokens)
+    }
+
+    /// Number of tokens `text` encodes to
+    pub fn count_tokens(&self, text: &str) -> Result<usize, WhisperError> {
+        self.tokenize(text).map(|tokens| tokens.len())
+    }
+
+    /// Raw bytes of a token. Arabic and other non-Latin characters are often split
+    /// across tokens, so a single token may not be valid UTF-8.
+    pub fn token_bytes(&self, token: TokenId) -> Option<&[u8]> {
+        if token < 0 || token as usize >= self.n_vocab() {
+            return None;
+        }
+
+        // Points into the vocabulary, which lives as long as the model
+        let text_ptr = unsafe { ffi::whisper_token_to_str(self.model.ctx(), token) };
+        if text_ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(text_ptr) }.to_bytes())
+        }
+    }
+
+    /// Text of a token, with partial UTF-8 sequences replaced; special tokens
+    /// come out as whisper.cpp names them, e.g. "[_SOT_]"
+    pub fn token_to_str(&self, token: TokenId) -> Option<String> {
+        self.token_bytes(token).map(|bytes| String::from_utf8_lossy(bytes).to_string())
+    }
+
+    /// Text of a token sequence. Special tokens are skipped, and bytes are joined
+    /// before decoding so characters split across tokens come out whole.
+    pub fn detokenize(&self, tokens: &[TokenId]) -> String {
+        let bytes: Vec<u8> = tokens
+            .iter()
+            .filter(|&&token| !self.is_special(token))
+            .filter_map(|&token| self.token_bytes(token))
+            .flatten()
+            .copied()
+            .collect();
+        String::from_utf8_lossy(&bytes).to_string()
+    }
+
+    /// Every token with its bytes, in id order
+    pub fn vocab(&self) -> impl Iterator<Item = (TokenId, &[u8])> + '_ {
+        (0..self.n_vocab() as TokenId).filter_map(move |token| self.token_bytes(token).map(|bytes| (token, bytes)))
+    }
+
+    pub fn special_tokens(&self) -> SpecialTokens {
+        let ctx = self.model.ctx();
+        unsafe {
+            SpecialTokens {
+                eot: ffi::whisper_token_eot(ctx),
+                sot: ffi::whisper_token_sot(ctx),
+                sot_prev: ffi::whisper_token_prev(ctx),
+                sot_lm: ffi::whisper_token_solm(ctx),
+                no_speech: ffi::whisper_token_nosp(ctx),
+                no_timestamps: ffi::whisper_token_not(ctx),
+                timestamp_begin: ffi::whisper_token_beg(ctx),
+                translate: ffi::whisper_token_translate(ctx),
+                transcribe: ffi::whisper_token_transcribe(ctx),
+            }
+        }
+    }
+
+    /// Whether `token` is a special token rather than text
+    pub fn is_special(&self, token: TokenId) -> bool {
+        token >= unsafe { ffi::whisper_token_eot(self.model.ctx()) }
+    }
+
+    /// Token of a language code such as "ar"; `None` for unknown codes and English-only models
+    pub fn language_token(&self, code: &str) -> Option<TokenId> {
+        let code_c = CString::new(code).ok()?;
+        let lang_id = unsafe { ffi::whisper_lang_id(code_c.as_ptr()) };
+        let multilingual = unsafe { ffi::whisper_is_multilingual(self.model.ctx()) } != 0;
+        (lang_id >= 0 && multilingual).then(|| unsafe { ffi::whisper_token_lang(self.model.ctx(), lang_id) })
+    }
+
+    /// Timestamp token for a time within a 30 s window, rounded down to 20 ms
+    pub fn timestamp_token(&self, ms: i64) -> Option<TokenId> {
+        (0..=MAX_TIMESTAMP_MS).contains(&ms).then(|| self.special_tokens().timestamp_begin + (ms / TIMESTAMP_STEP_MS) as TokenId)
+    }
+
+    /// Time of a timestamp token, or `None` for any other token
+    pub fn timestamp_ms(&self, token: TokenId) -> Option<i64> {
+        let offset = (token - self.special_tokens().timestamp_begin) as i64 * TIMESTAMP_STEP_MS;
+        (0..=MAX_TIMESTAMP_MS).contains(&offset).then_some(offset)
+    }
+
+    /// Longest prompt whisper.cpp keeps: half the text context (224 tokens for the
+    /// released models). Longer prompts lose their oldest tokens.
+    pub fn max_prompt_tokens(&self) -> usize {
+        (unsafe { ffi::whisper_model_n_text_ctx(self.model.ctx()) } / 2).max(0) as usize
+    }
+}
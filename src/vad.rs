@@ -0,0 +1,357 @@
+//! Model-free voice activity detection
+//!
+//! Each frame is classified from three cheap features:
+//! - energy relative to an adaptive noise floor
+//! - zero-crossing rate (noise and hiss cross zero far more often than voiced speech)
+//! - spectral flatness (close to 1 for noise, low for harmonic speech)
+//!
+//! Frame decisions are smoothed with hysteresis: a region opens after a few
+//! consecutive speech frames and closes only after a hangover of silence.
+
+use serde::{Deserialize, Serialize};
+use crate::fft::Fft;
+use crate::SpeechRegion;
+
+/// Tuning for `VoiceActivityDetector`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VadConfig {
+    pub sample_rate: u32,
+    /// Analysis frame length
+    pub frame_ms: u32,
+    /// A frame needs at least this many times the noise-floor energy to count as speech
+    pub energy_ratio: f32,
+    /// Absolute energy (mean square) below which a frame is never speech
+    pub min_energy: f32,
+    /// Frames with a zero-crossing rate (crossings per sample) below this look voiced
+    pub zcr_threshold: f32,
+    /// Frames with a spectral flatness below this look harmonic
+    pub flatness_threshold: f32,
+    /// How fast the noise floor follows non-speech frames (0..1)
+    pub noise_adapt_rate: f32,
+    /// Consecutive speech frames needed to open a region
+    pub speech_start_frames: u32,
+    /// Silence needed to close a region
+    pub hangover_ms: u32,
+    /// Regions shorter than this are dropped
+    pub min_speech_ms: u32,
+    /// Padding added on both sides of each region
+    pub speech_pad_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 16000,
+            frame_ms: 30,
+            energy_ratio: 3.0,
+            min_energy: 1e-6,
+            zcr_threshold: 0.25,
+            flatness_threshold: 0.3,
+            noise_adapt_rate: 0.05,
+            speech_start_frames: 3,
+            hangover_ms: 300,
+            min_speech_ms: 100,
+            speech_pad_ms: 100,
+        }
+    }
+}
+
+/// Features of a single analysis frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameFeatures {
+    /// Mean square amplitude
+    pub energy: f32,
+    /// Zero crossings per sample
+    pub zcr: f32,
+    /// Geometric over arithmetic mean of the power spectrum
+    pub flatness: f32,
+}
+
+/// Adaptive energy + zero-crossing + spectral-flatness detector.
+///
+/// The noise floor is kept between calls to `detect`, so reusing one detector
+/// for consecutive windows of the same stream gives steadier decisions.
+#[derive(Debug, Clone)]
+pub struct VoiceActivityDetector {
+    config: VadConfig,
+    noise_floor: Option<f32>,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(config: VadConfig) -> Self {
+        Self {
+            config,
+            noise_floor: None,
+        }
+    }
+
+    pub fn config(&self) -> &VadConfig {
+        &self.config
+    }
+
+    /// Forget the learned noise floor (e.g. when the microphone changes)
+    pub fn reset(&mut self) {
+        self.noise_floor = None;
+    }
+
+    fn frame_len(&self) -> usize {
+        ((self.config.sample_rate as u64 * self.config.frame_ms as u64 / 1000) as usize).max(1)
+    }
+
+    fn ms_to_frames(&self, ms: u32) -> usize {
+        (ms as usize).div_ceil(self.config.frame_ms.max(1) as usize)
+    }
+
+    fn frames_to_ms(&self, frames: usize) -> i64 {
+        frames as i64 * self.config.frame_ms as i64
+    }
+
+    /// Classify one frame and update the noise floor
+    fn is_speech_frame(&mut self, features: &FrameFeatures) -> bool {
+        let config = &self.config;
+        let noise_floor = *self.noise_floor.get_or_insert(config.min_energy);
+
+        let loud = features.energy >= config.min_energy
+            && features.energy > noise_floor * config.energy_ratio;
+        let voiced = features.zcr < config.zcr_threshold || features.flatness < config.flatness_threshold;
+        let speech = loud && voiced;
+
+        // Drop straight to quieter frames and follow louder ones slowly; speech frames
+        // adapt ten times slower so steady hum is eventually absorbed but words are not
+        let rate = if speech { config.noise_adapt_rate / 10.0 } else { config.noise_adapt_rate };
+        let updated = if features.energy < noise_floor {
+            features.energy
+        } else {
+            noise_floor + rate * (features.energy - noise_floor)
+        };
+        self.noise_floor = Some(updated.max(config.min_energy));
+
+        speech
+    }
+
+    /// Speech regions in `samples`, in milliseconds from their start
+    pub fn detect(&mut self, samples: &[f32]) -> Vec<SpeechRegion> {
+        let frame_len = self.frame_len();
+        let mut fft = Fft::new(frame_len.next_power_of_two());
+        let decisions: Vec<bool> = samples
+            .chunks(frame_len)
+            .filter(|frame| frame.len() == frame_len)
+            .map(|frame| {
+                let features = features_with(frame, &mut fft);
+                self.is_speech_frame(&features)
+            })
+            .collect();
+
+        self.smooth(&decisions, samples.len())
+    }
+
+    /// Whether `samples` contain any speech region
+    pub fn contains_speech(&mut self, samples: &[f32]) -> bool {
+        !self.detect(samples).is_empty()
+    }
+
+    /// Turn per-frame decisions into padded, merged regions
+    fn smooth(&self, decisions: &[bool], n_samples: usize) -> Vec<SpeechRegion> {
+        let start_frames = self.config.speech_start_frames.max(1) as usize;
+        let hangover_frames = self.ms_to_frames(self.config.hangover_ms);
+
+        let mut frame_regions: Vec<(usize, usize)> = Vec::new();
+        let mut run_start = None;
+        let mut run_len = 0;
+        let mut open: Option<usize> = None;
+        let mut last_speech = 0;
+
+        for (i, &speech) in decisions.iter().enumerate() {
+            match open {
+                None if speech => {
+                    let start = *run_start.get_or_insert(i);
+                    run_len += 1;
+                    if run_len >= start_frames {
+                        open = Some(start);
+                        last_speech = i;
+                    }
+                }
+                None => {
+                    run_start = None;
+                    run_len = 0;
+                }
+                Some(start) => {
+                    if speech {
+                        last_speech = i;
+                    } else if i - last_speech > hangover_frames {
+                        frame_regions.push((start, last_speech + 1));
+                        open = None;
+                        run_start = None;
+                        run_len = 0;
+                    }
+                }
+            }
+        }
+        if let Some(start) = open {
+            frame_regions.push((start, last_speech + 1));
+        }
+
+        let total_ms = n_samples as i64 * 1000 / self.config.sample_rate.max(1) as i64;
+        let pad_ms = self.config.speech_pad_ms as i64;
+        let min_ms = self.config.min_speech_ms as i64;
+
+        let mut regions: Vec<SpeechRegion> = Vec::new();
+        for (start, end) in frame_regions {
+            let start_ms = self.frames_to_ms(start);
+            let end_ms = self.frames_to_ms(end);
+            if end_ms - start_ms < min_ms {
+                continue;
+            }
+
+            let region = SpeechRegion {
+                start_ms: (start_ms - pad_ms).max(0),
+                end_ms: (end_ms + pad_ms).min(total_ms),
+            };
+            match regions.last_mut() {
+                Some(last) if region.start_ms <= last.end_ms => last.end_ms = last.end_ms.max(region.end_ms),
+                _ => regions.push(region),
+            }
+        }
+
+        regions
+    }
+}
+
+impl Default for VoiceActivityDetector {
+    fn default() -> Self {
+        Self::new(VadConfig::default())
+    }
+}
+
+/// Energy, zero-crossing rate and spectral flatness of one frame
+pub fn frame_features(frame: &[f32]) -> FrameFeatures {
+    features_with(frame, &mut Fft::new(frame.len().next_power_of_two()))
+}
+
+/// `frame_features` with a transform of `frame.len().next_power_of_two()` points
+fn features_with(frame: &[f32], fft: &mut Fft) -> FrameFeatures {
+    if frame.is_empty() {
+        return FrameFeatures { energy: 0.0, zcr: 0.0, flatness: 1.0 };
+    }
+
+    let energy = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+
+    let crossings = frame
+        .windows(2)
+        .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+        .count();
+    let zcr = crossings as f32 / frame.len() as f32;
+
+    FrameFeatures {
+        energy,
+        zcr,
+        flatness: spectral_flatness(frame, fft),
+    }
+}
+
+/// Geometric over arithmetic mean of the Hann-windowed power spectrum (DC excluded)
+fn spectral_flatness(frame: &[f32], fft: &mut Fft) -> f32 {
+    let n = fft.len();
+    let mut re = vec![0.0f32; n];
+    let mut im = vec![0.0f32; n];
+    let len = frame.len();
+    for (i, &sample) in frame.iter().enumerate() {
+        let hann = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (len.max(2) - 1) as f32).cos();
+        re[i] = sample * hann;
+    }
+
+    fft.process(&mut re, &mut im);
+
+    // Small offset keeps log() finite for digital silence
+    let power: Vec<f32> = (1..n / 2)
+        .map(|k| re[k] * re[k] + im[k] * im[k] + 1e-12)
+        .collect();
+    if power.is_empty() {
+        return 1.0;
+    }
+
+    let log_mean = power.iter().map(|p| p.ln()).sum::<f32>() / power.len() as f32;
+    let mean = power.iter().sum::<f32>() / power.len() as f32;
+    (log_mean.exp() / mean).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 16000;
+
+    /// Voiced-speech stand-in: 150 Hz fundamental with a few harmonics
+    fn voiced(ms: usize, amplitude: f32) -> Vec<f32> {
+        (0..SAMPLE_RATE * ms / 1000)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                (1..=4)
+                    .map(|h| (2.0 * std::f32::consts::PI * 150.0 * h as f32 * t).sin() / h as f32)
+                    .sum::<f32>()
+                    * amplitude
+            })
+            .collect()
+    }
+
+    /// Deterministic white noise
+    fn noise(ms: usize, amplitude: f32, seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        (0..SAMPLE_RATE * ms / 1000)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                ((state >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_features_separate_tone_from_noise() {
+        let tone = frame_features(&voiced(30, 0.3));
+        let hiss = frame_features(&noise(30, 0.3, 7));
+
+        assert!(tone.zcr < 0.1);
+        assert!(tone.flatness < 0.1);
+        assert!(hiss.zcr > 0.3);
+        assert!(hiss.flatness > 0.3);
+    }
+
+    #[test]
+    fn test_detects_speech_between_silence() {
+        let mut audio = noise(1000, 0.001, 1);
+        audio.extend(voiced(1000, 0.3));
+        audio.extend(noise(1000, 0.001, 2));
+
+        let mut detector = VoiceActivityDetector::default();
+        let regions = detector.detect(&audio);
+
+        assert_eq!(regions.len(), 1);
+        let pad = detector.config().speech_pad_ms as i64;
+        let frame = detector.config().frame_ms as i64;
+        assert!((regions[0].start_ms - (1000 - pad)).abs() <= frame);
+        assert!((regions[0].end_ms - (2000 + pad)).abs() <= frame);
+    }
+
+    #[test]
+    fn test_silence_and_hiss_are_not_speech() {
+        let mut detector = VoiceActivityDetector::default();
+        assert!(!detector.contains_speech(&vec![0.0; SAMPLE_RATE]));
+
+        let mut audio = noise(500, 0.001, 3);
+        audio.extend(noise(1000, 0.2, 4));
+        assert!(!detector.contains_speech(&audio));
+    }
+
+    #[test]
+    fn test_hangover_bridges_short_pauses() {
+        let mut audio = noise(500, 0.001, 5);
+        audio.extend(voiced(400, 0.3));
+        audio.extend(noise(150, 0.001, 6));
+        audio.extend(voiced(400, 0.3));
+        audio.extend(noise(500, 0.001, 7));
+
+        let regions = VoiceActivityDetector::default().detect(&audio);
+        assert_eq!(regions.len(), 1);
+    }
+}
@@ -0,0 +1,542 @@
+//! Safe Rust API: a loaded model and the sessions that decode with it
+//!
+//! `Whisper` holds a share of the model weights and `Session` owns everything a
+//! single transcription needs. Both free their whisper.cpp resources on drop.
+//! The instance-id functions, the C ABI, JNI and Flutter layers are built on these.
+
+use std::ffi::{c_int, c_void, CString};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use log::debug;
+use crate::callbacks::Callbacks;
+use crate::cancel::{AbortReason, AbortSignal};
+use crate::{
+    ffi, is_known_language, language_code, log_mel_spectrogram_with, read_segment, CancellationToken, ContextOptions,
+    Mel, Model, Segment, SpeechRegion, Tokenizer, TranscribeOptions, Transcript, VadOptions, WhisperError,
+};
+use crate::model::PooledState;
+
+/// A loaded model. Clones share the weights; every session created from it
+/// decodes on its own state, so sessions can run on different threads at once.
+#[derive(Clone)]
+pub struct Whisper {
+    model: Arc<Model>,
+}
+
+impl Whisper {
+    /// Load a model file, sharing the weights with any `Whisper` already loaded
+    /// from the same path with the same options
+    pub fn load(model_path: impl AsRef<Path>, options: ContextOptions) -> Result<Self, WhisperError> {
+        let model_path = model_path.as_ref();
+        if !model_path.exists() {
+            return Err(WhisperError::ModelInitError(format!("Model file not found: {}", model_path.display())));
+        }
+
+        let model_path = model_path
+            .to_str()
+            .ok_or_else(|| WhisperError::ModelInitError("Invalid model path".to_string()))?;
+
+        Ok(Self::from_model(Model::load(model_path, &options)?))
+    }
+
+    /// Load a model held in memory; see `Model::load_from_bytes`
+    pub fn load_from_bytes(bytes: &[u8], options: ContextOptions) -> Result<Self, WhisperError> {
+        Ok(Self::from_model(Model::load_from_bytes(bytes, &options)?))
+    }
+
+    /// Load a model streamed from a reader; see `Model::load_from_reader`
+    pub fn load_from_reader(reader: impl std::io::Read, options: ContextOptions) -> Result<Self, WhisperError> {
+        Ok(Self::from_model(Model::load_from_reader(reader, &options)?))
+    }
+
+    /// Load a model stored at `offset` in an open file; see `Model::load_from_fd`
+    #[cfg(unix)]
+    pub fn load_from_fd(fd: std::os::unix::io::RawFd, offset: u64, options: ContextOptions) -> Result<Self, WhisperError> {
+        Ok(Self::from_model(Model::load_from_fd(fd, offset, &options)?))
+    }
+
+    pub fn from_model(model: Arc<Model>) -> Self {
+        Self { model }
+    }
+
+    pub fn model(&self) -> &Arc<Model> {
+        &self.model
+    }
+
+    /// Tokenizer over this model's vocabulary
+    pub fn tokenizer(&self) -> Tokenizer {
+        Tokenizer::new(Arc::clone(&self.model))
+    }
+
+    /// Start a new decoding session on this model
+    pub fn session(&self) -> Session {
+        Session::new(Arc::clone(&self.model))
+    }
+}
+
+impl fmt::Debug for Whisper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Whisper")
+            .field("model_path", &self.model.model_path())
+            .field("options", self.model.options())
+            .finish()
+    }
+}
+
+/// What a decoding call runs on
+#[derive(Clone, Copy)]
+enum Input<'a> {
+    Pcm(&'a [f32]),
+    Mel(&'a Mel),
+}
+
+impl Input<'_> {
+    /// Compute or copy the log-mel spectrogram into `state`
+    fn load_mel(self, state: &PooledState, n_threads: i32) -> Result<(), WhisperError> {
+        let result = match self {
+            Input::Pcm(audio) => unsafe {
+                ffi::whisper_pcm_to_mel_with_state(state.ctx(), state.ptr(), audio.as_ptr(), audio.len() as c_int, n_threads)
+            },
+            Input::Mel(mel) => {
+                let data = mel.padded();
+                let n_len = data.len() / mel.n_mel;
+                unsafe { ffi::whisper_set_mel_with_state(state.ctx(), state.ptr(), data.as_ptr(), n_len as c_int, mel.n_mel as c_int) }
+            }
+        };
+        if result != 0 {
+            return Err(WhisperError::ProcessingError(format!("Failed to compute mel spectrogram: {}", result)));
+        }
+        Ok(())
+    }
+}
+
+/// A decoding session on a loaded model. Each session has its own cancellation
+/// token and VAD model; sessions of the same `Whisper` decode in parallel.
+pub struct Session {
+    // Shared with every other session on the same model
+    model: Arc<Model>,
+    // Also registered in INSTANCE_HANDLES when the session backs a C instance
+    cancellation: CancellationToken,
+    // Silero VAD model loaded on first use by detect_speech
+    vad: Option<VadContext>,
+}
+
+/// A loaded whisper.cpp VAD model
+struct VadContext {
+    vctx: *mut c_void,
+    model_path: String,
+}
+
+impl VadContext {
+    fn new(model_path: &str, n_threads: i32) -> Result<Self, WhisperError> {
+        let model_path_c = CString::new(model_path)
+            .map_err(|_| WhisperError::ModelInitError("Invalid VAD model path".to_string()))?;
+
+        let mut params = unsafe { ffi::whisper_vad_default_context_params() };
+        params.n_threads = n_threads;
+
+        let vctx = unsafe { ffi::whisper_vad_init_from_file_with_params(model_path_c.as_ptr(), params) };
+        if vctx.is_null() {
+            return Err(WhisperError::ModelInitError(format!("Failed to load VAD model from {}", model_path)));
+        }
+
+        Ok(VadContext {
+            vctx,
+            model_path: model_path.to_string(),
+        })
+    }
+}
+
+impl Drop for VadContext {
+    fn drop(&mut self) {
+        unsafe { ffi::whisper_vad_free(self.vctx) };
+    }
+}
+
+
+unsafe impl Send for Session {}
+unsafe impl Sync for Session {}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("model_path", &self.model.model_path())
+            .field("vad_model_path", &self.vad.as_ref().map(|vad| &vad.model_path))
+            .finish()
+    }
+}
+
+impl Session {
+    fn new(model: Arc<Model>) -> Self {
+        Session {
+            model,
+            cancellation: CancellationToken::new(),
+            vad: None,
+        }
+    }
+
+    /// The model this session decodes with
+    pub fn model(&self) -> &Arc<Model> {
+        &self.model
+    }
+
+    /// Token that cancels this session's running call from another thread.
+    /// It is reset at the start of every call.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Transcribe audio (16 kHz mono f32)
+    pub fn transcribe(&mut self, audio_data: &[f32], options: &TranscribeOptions) -> Result<Vec<Segment>, WhisperError> {
+        if audio_data.is_empty() {
+            return Err(WhisperError::InvalidAudioData);
+        }
+
+        self.cancellation.reset();
+        self.process_audio_until(Input::Pcm(audio_data), options, options.deadline(), None)
+    }
+
+    /// Log-mel spectrogram of audio (16 kHz mono f32) through the filter bank stored in
+    /// the model, as whisper.cpp computes it before encoding
+    pub fn compute_mel(&self, audio_data: &[f32]) -> Result<Mel, WhisperError> {
+        log_mel_spectrogram_with(audio_data, self.model.mel_filters())
+    }
+
+    /// Transcribe a log-mel spectrogram from `compute_mel` or another front-end instead
+    /// of audio. It must have the model's number of mel bands and whisper's normalization;
+    /// segment times count from its first frame. VAD needs audio, so `options.vad` must be unset.
+    pub fn transcribe_mel(&mut self, mel: &Mel, options: &TranscribeOptions) -> Result<Vec<Segment>, WhisperError> {
+        let n_mels = self.model.info().n_mels as usize;
+        if mel.n_mel != n_mels {
+            return Err(WhisperError::InvalidParameter(format!(
+                "Spectrogram has {} mel bands, the model expects {}",
+                mel.n_mel, n_mels
+            )));
+        }
+        if mel.n_frames == 0 || mel.data.len() != mel.n_mel * mel.n_frames {
+            return Err(WhisperError::InvalidAudioData);
+        }
+        if options.vad.is_some() {
+            return Err(WhisperError::InvalidParameter("VAD can't run on a spectrogram".to_string()));
+        }
+
+        self.cancellation.reset();
+        self.process_audio_until(Input::Mel(mel), options, options.deadline(), None)
+    }
+
+    /// Transcribe while reporting progress (0-100) and each segment as soon as it
+    /// is decoded. Both closures run on the calling thread, inside the whisper call;
+    /// a panic in either is re-raised after the call returns.
+    pub fn transcribe_with_callbacks(
+        &mut self,
+        audio_data: &[f32],
+        options: &TranscribeOptions,
+        mut on_progress: impl FnMut(u8),
+        mut on_segment: impl FnMut(&Segment),
+    ) -> Result<Vec<Segment>, WhisperError> {
+        if audio_data.is_empty() {
+            return Err(WhisperError::InvalidAudioData);
+        }
+
+        let mut callbacks = Callbacks::new(&mut on_progress, &mut on_segment);
+        self.cancellation.reset();
+        self.process_audio_until(Input::Pcm(audio_data), options, options.deadline(), Some(&mut callbacks))
+    }
+
+    /// Run Silero VAD over the audio; the VAD model is loaded once and kept
+    pub fn detect_speech(&mut self, audio_data: &[f32], vad: &VadOptions, n_threads: i32) -> Result<Vec<SpeechRegion>, WhisperError> {
+        if audio_data.is_empty() {
+            return Err(WhisperError::InvalidAudioData);
+        }
+
+        vad.validate()?;
+
+        if self.vad.as_ref().is_none_or(|loaded| loaded.model_path != vad.model_path) {
+            self.vad = Some(VadContext::new(&vad.model_path, n_threads)?);
+        }
+        let Some(loaded) = self.vad.as_ref() else {
+            return Err(WhisperError::InternalError("VAD model not loaded".to_string()));
+        };
+
+        let segments = unsafe {
+            ffi::whisper_vad_segments_from_samples(loaded.vctx, vad.to_raw(), audio_data.as_ptr(), audio_data.len() as c_int)
+        };
+        if segments.is_null() {
+            return Err(WhisperError::ProcessingError("Voice activity detection failed".to_string()));
+        }
+
+        let regions = unsafe {
+            let n_segments = ffi::whisper_vad_segments_n_segments(segments);
+            let regions = (0..n_segments)
+                .map(|i| SpeechRegion {
+                    start_ms: (ffi::whisper_vad_segments_get_segment_t0(segments, i) * 10.0) as i64,
+                    end_ms: (ffi::whisper_vad_segments_get_segment_t1(segments, i) * 10.0) as i64,
+                })
+                .collect();
+            ffi::whisper_vad_free_segments(segments);
+            regions
+        };
+
+        Ok(regions)
+    }
+
+    /// Transcribe with `options.vad` (required) and report the detected speech regions.
+    /// Audio without speech is not sent to whisper at all.
+    pub fn transcribe_with_speech_regions(&mut self, audio_data: &[f32], options: &TranscribeOptions) -> Result<Transcript, WhisperError> {
+        let vad = options.vad
+            .as_ref()
+            .ok_or_else(|| WhisperError::InvalidParameter("VAD options are required to report speech regions".to_string()))?;
+
+        let speech_regions = self.detect_speech(audio_data, vad, options.n_threads)?;
+        let segments = if speech_regions.is_empty() {
+            Vec::new()
+        } else {
+            // VAD already ran; don't make whisper.cpp run it again on the same audio
+            let options = TranscribeOptions { vad: None, ..options.clone() };
+            self.transcribe(audio_data, &options)?
+        };
+
+        Ok(Transcript { segments, speech_regions })
+    }
+
+    /// Run one `whisper_full_with_state` call that aborts on cancellation or at `deadline`
+    fn process_audio_until(
+        &mut self,
+        input: Input,
+        options: &TranscribeOptions,
+        deadline: Option<Instant>,
+        mut callbacks: Option<&mut Callbacks>,
+    ) -> Result<Vec<Segment>, WhisperError> {
+        let mut tokens = vec![self.cancellation.clone()];
+        tokens.extend(options.cancellation.clone());
+        if tokens.iter().any(CancellationToken::is_cancelled) {
+            return Err(WhisperError::Cancelled(Vec::new()));
+        }
+
+        match input {
+            Input::Pcm(audio_data) => debug!("Transcribing {} samples", audio_data.len()),
+            Input::Mel(mel) => debug!("Transcribing {} mel frames", mel.n_frames),
+        }
+
+        // Restrict auto-detection to the allowed languages by detecting up front
+        let restricted;
+        let options = match self.pick_allowed_language(input, options)? {
+            Some(language) => {
+                restricted = options.clone().language(language);
+                &restricted
+            }
+            None => options,
+        };
+
+        let mut params = options.to_full_params(Tokenizer::new(Arc::clone(&self.model)).n_vocab())?;
+        params.set_abort_signal(AbortSignal::new(tokens, deadline));
+        if let Some(callbacks) = callbacks.as_deref_mut() {
+            params.set_callbacks(callbacks);
+        }
+        if let Some(language) = options.language.as_deref() {
+            debug!("Language set to: {}", language);
+        }
+
+        // Decode on a pooled state so other instances of the model can run meanwhile
+        let state = self.model.acquire_state()?;
+        let ctx = state.ctx();
+
+        // A spectrogram is set on the state up front and decoded without samples;
+        // its padding must not be decoded as audio
+        let (samples, n_samples) = match input {
+            Input::Pcm(audio_data) => (audio_data.as_ptr(), audio_data.len() as c_int),
+            Input::Mel(mel) => {
+                input.load_mel(&state, options.n_threads)?;
+                params.raw.duration_ms = mel.duration_ms() as c_int;
+                (std::ptr::null(), 0)
+            }
+        };
+
+        // Process audio
+        let result = unsafe {
+            ffi::whisper_full_with_state(
+                ctx,
+                state.ptr(),
+                params.raw,
+                samples,
+                n_samples
+            )
+        };
+
+        debug!("whisper_full_with_state returned {}", result);
+
+        if let Some(callbacks) = callbacks {
+            callbacks.resume_panic();
+        }
+        params.resume_logits_filter_panic();
+
+        // An aborted encoder makes whisper.cpp stop without reporting an error,
+        // so check the abort reason before the return code
+        if let Some(reason) = params.abort_reason() {
+            let n_segments = unsafe { ffi::whisper_full_n_segments_from_state(state.ptr()) };
+            let completed = (0..n_segments).filter_map(|i| read_segment(ctx, state.ptr(), i)).collect();
+            return Err(match reason {
+                AbortReason::Cancelled => WhisperError::Cancelled(completed),
+                AbortReason::DeadlineExceeded => WhisperError::DeadlineExceeded(completed),
+            });
+        }
+
+        if result != 0 {
+            return Err(WhisperError::ProcessingError(format!("Failed to process audio: {}", result)));
+        }
+
+        // Extract results using state-based functions
+        let n_segments = unsafe { ffi::whisper_full_n_segments_from_state(state.ptr()) };
+        debug!("Found {} segments", n_segments);
+
+        let segments = (0..n_segments)
+            .filter_map(|i| read_segment(ctx, state.ptr(), i))
+            .collect();
+
+        Ok(segments)
+    }
+
+    /// Detect the spoken language; returns `(code, probability)` pairs, most likely first
+    pub fn detect_language(&self, audio_data: &[f32], n_threads: i32) -> Result<Vec<(String, f32)>, WhisperError> {
+        if audio_data.is_empty() {
+            return Err(WhisperError::InvalidAudioData);
+        }
+
+        self.detect_language_in(Input::Pcm(audio_data), n_threads)
+    }
+
+    fn detect_language_in(&self, input: Input, n_threads: i32) -> Result<Vec<(String, f32)>, WhisperError> {
+        let state = self.model.acquire_state()?;
+        input.load_mel(&state, n_threads)?;
+
+        let n_languages = unsafe { ffi::whisper_lang_max_id() } + 1;
+        let mut probs = vec![0.0f32; n_languages.max(0) as usize];
+
+        let lang_id = unsafe {
+            ffi::whisper_lang_auto_detect_with_state(state.ctx(), state.ptr(), 0, n_threads, probs.as_mut_ptr())
+        };
+        if lang_id < 0 {
+            return Err(WhisperError::ProcessingError(format!("Language detection failed: {}", lang_id)));
+        }
+
+        let mut languages: Vec<(String, f32)> = probs
+            .iter()
+            .enumerate()
+            .filter_map(|(id, &prob)| language_code(id as c_int).map(|code| (code, prob)))
+            .collect();
+        languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        Ok(languages)
+    }
+
+    /// With `allowed_languages` set and no explicit language, pick the most likely allowed one
+    fn pick_allowed_language(&self, input: Input, options: &TranscribeOptions) -> Result<Option<String>, WhisperError> {
+        let auto = matches!(options.language.as_deref(), None | Some("") | Some("auto"));
+        if !auto || options.allowed_languages.is_empty() {
+            return Ok(None);
+        }
+
+        for language in &options.allowed_languages {
+            if !is_known_language(language) {
+                return Err(WhisperError::InvalidParameter(format!("Unknown language: {}", language)));
+            }
+        }
+
+        let detected = self.detect_language_in(input, options.n_threads)?;
+        Ok(detected
+            .into_iter()
+            .find(|(code, _)| options.allowed_languages.iter().any(|allowed| allowed == code))
+            .map(|(code, _)| code))
+    }
+
+    /// Transcribe overlapping windows of `window_size_sec`, `step_size_sec` apart.
+    /// Segment times are relative to the start of `audio_data`.
+    pub fn transcribe_sliding_window(
+        &mut self, 
+        audio_data: &[f32], 
+        window_size_sec: f32,
+        step_size_sec: f32,
+        sample_rate: i32,
+        options: &TranscribeOptions
+    ) -> Result<Vec<Segment>, WhisperError> {
+        if window_size_sec <= 0.0 || step_size_sec <= 0.0 || step_size_sec > window_size_sec {
+            return Err(WhisperError::ProcessingError("Invalid window or step size".to_string()));
+        }
+
+        if sample_rate <= 0 {
+            return Err(WhisperError::ProcessingError("Invalid sample rate".to_string()));
+        }
+
+        let window_samples = (window_size_sec * sample_rate as f32) as usize;
+        let step_samples = (step_size_sec * sample_rate as f32) as usize;
+
+        if window_samples >= audio_data.len() {
+            // If audio is shorter than one window, process the entire audio
+            return self.transcribe(audio_data, options);
+        }
+
+        // One deadline and one cancellation for the whole call, not per window
+        self.cancellation.reset();
+        let deadline = options.deadline();
+
+        let mut all_segments = Vec::new();
+        let mut position = 0;
+
+        while position + window_samples <= audio_data.len() {
+            let window = &audio_data[position..position + window_samples];
+            let offset_ms = position as i64 * 1000 / sample_rate as i64;
+            let segments = self.process_audio_until(Input::Pcm(window), options, deadline, None)
+                .map_err(|e| e.offset_partial(offset_ms).with_earlier_segments(std::mem::take(&mut all_segments)))?;
+
+            for segment in segments {
+                all_segments.push(segment.offset(offset_ms));
+            }
+
+            position += step_samples;
+        }
+
+        // Process the last window if there's remaining audio
+        if position < audio_data.len() && audio_data.len() - position > step_samples / 2 {
+            let window_start = audio_data.len() - window_samples.min(audio_data.len());
+            let window = &audio_data[window_start..audio_data.len()];
+            let offset_ms = window_start as i64 * 1000 / sample_rate as i64;
+            let segments = self.process_audio_until(Input::Pcm(window), options, deadline, None)
+                .map_err(|e| e.offset_partial(offset_ms).with_earlier_segments(std::mem::take(&mut all_segments)))?;
+
+            for segment in segments {
+                all_segments.push(segment.offset(offset_ms));
+            }
+        }
+
+        Ok(all_segments)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_missing_model() {
+        let err = Whisper::load("models/does-not-exist.bin", ContextOptions::default()).unwrap_err();
+        assert!(matches!(err, WhisperError::ModelInitError(_)));
+    }
+
+    #[test]
+    fn test_load_from_invalid_sources() {
+        let err = Whisper::load_from_bytes(&[], ContextOptions::default()).unwrap_err();
+        assert!(matches!(err, WhisperError::ModelInitError(_)));
+
+        let err = Whisper::load_from_bytes(b"not a ggml model", ContextOptions::default()).unwrap_err();
+        assert!(matches!(err, WhisperError::UnsupportedModelFormat(_)));
+
+        #[cfg(unix)]
+        {
+            let err = Whisper::load_from_fd(-1, 0, ContextOptions::default()).unwrap_err();
+            assert!(matches!(err, WhisperError::ModelInitError(_)));
+        }
+    }
+}